@@ -0,0 +1,56 @@
+/// A document structuring comment, e.g. `%%BoundingBox: 0 0 612 792`.
+///
+/// See the Document Structuring Conventions Specification, version 3.0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DscComment {
+    /// The 1-based line the comment starts on.
+    pub line: usize,
+    /// The keyword following `%%`, without a trailing colon.
+    pub keyword: String,
+    /// Everything after the keyword, with surrounding whitespace removed.
+    pub value: Option<String>,
+}
+
+impl DscComment {
+    /// Parse the text of a comment with the leading `%%` already stripped.
+    pub fn parse(line: usize, text: &str) -> Self {
+        let text = text.trim_end();
+        let (keyword, value) = match text.find(|c: char| c == ':' || c.is_whitespace()) {
+            Some(pos) => {
+                let value = text[pos..].trim_start_matches(':').trim();
+                (&text[..pos], (!value.is_empty()).then(|| value.to_string()))
+            }
+            None => (text, None),
+        };
+
+        DscComment {
+            line,
+            keyword: keyword.to_string(),
+            value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_keyword_and_value() {
+        let comment = DscComment::parse(2, "BoundingBox: 0 0 612 792");
+        assert_eq!(2, comment.line);
+        assert_eq!("BoundingBox", comment.keyword);
+        assert_eq!(Some("0 0 612 792".to_string()), comment.value);
+    }
+
+    #[test]
+    fn parses_keyword_without_value() {
+        let comment = DscComment::parse(1, "EndComments");
+        assert_eq!("EndComments", comment.keyword);
+        assert_eq!(None, comment.value);
+
+        let comment = DscComment::parse(1, "Trailer:  ");
+        assert_eq!("Trailer", comment.keyword);
+        assert_eq!(None, comment.value);
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::io;
+
+use super::span::Span;
+
+/// A procedure or operator call that led to an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub span: Option<Span>,
+}
+
+macro_rules! errors {
+    ($($variant:ident => $name:literal,)*) => {
+        /// A PostScript error, carrying the command that raised it.
+        ///
+        /// The command is empty until the operator or name being executed is known.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum PsError {
+            $($variant(String),)*
+        }
+
+        impl PsError {
+            /// The names of all errors, which `errordict` has a default handler for.
+            pub const NAMES: &'static [&'static str] = &[$($name,)*];
+
+            /// The name of the error, as looked up in `errordict`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(PsError::$variant(_) => $name,)*
+                }
+            }
+
+            /// The operator or name that raised the error.
+            pub fn command(&self) -> &str {
+                match self {
+                    $(PsError::$variant(command) => command,)*
+                }
+            }
+
+            fn command_mut(&mut self) -> &mut String {
+                match self {
+                    $(PsError::$variant(command) => command,)*
+                }
+            }
+        }
+    };
+}
+
+errors! {
+    Dictstackunderflow => "dictstackunderflow",
+    Execstackoverflow => "execstackoverflow",
+    Invalidaccess => "invalidaccess",
+    Invalidexit => "invalidexit",
+    Invalidrestore => "invalidrestore",
+    Invalidstop => "invalidstop",
+    Ioerror => "ioerror",
+    Limitcheck => "limitcheck",
+    Rangecheck => "rangecheck",
+    Stackunderflow => "stackunderflow",
+    Syntaxerror => "syntaxerror",
+    Typecheck => "typecheck",
+    Undefined => "undefined",
+    Undefinedfilename => "undefinedfilename",
+    Undefinedresult => "undefinedresult",
+    Unmatchedmark => "unmatchedmark",
+}
+
+pub type Result<T, E = PsError> = std::result::Result<T, E>;
+
+impl PsError {
+    /// Attribute the error to `command`, unless it already names one.
+    pub(crate) fn in_command(mut self, command: &str) -> Self {
+        if self.command().is_empty() {
+            *self.command_mut() = command.to_string();
+        }
+        self
+    }
+}
+
+impl fmt::Display for PsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}", self.name())?;
+        if !self.command().is_empty() {
+            write!(f, " in {}", self.command())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PsError {}
+
+impl From<io::Error> for PsError {
+    fn from(_: io::Error) -> Self {
+        PsError::Ioerror(String::new())
+    }
+}
+
+/// An error with the location of the token that caused it.
+///
+/// Its `Display` implementation renders a report with a snippet of the source,
+/// followed by the chain of calls that led to the failing token, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    error: PsError,
+    span: Option<Span>,
+    trace: Vec<Frame>,
+}
+
+impl Error {
+    pub(crate) fn new(error: PsError, span: Option<Span>, trace: Vec<Frame>) -> Self {
+        Error { error, span, trace }
+    }
+
+    /// The PostScript error, e.g. `/undefined in foo`.
+    pub fn error(&self) -> &PsError {
+        &self.error
+    }
+
+    /// The location of the token that failed.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    /// The calls that led to the failing token, innermost first.
+    pub fn trace(&self) -> &[Frame] {
+        &self.trace
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+
+        if let Some(span) = &self.span {
+            let gutter = span.line.to_string().len();
+            write!(f, "\n{:gutter$}--> {}", "", span, gutter = gutter)?;
+            if let Some((line, column, len)) = span.snippet() {
+                let caret = "^".repeat(len.max(1));
+                write!(f, "\n{:gutter$} |", "", gutter = gutter)?;
+                write!(f, "\n{} | {}", span.line, line)?;
+                write!(
+                    f,
+                    "\n{:gutter$} | {:indent$}{}",
+                    "",
+                    "",
+                    caret,
+                    gutter = gutter,
+                    indent = column
+                )?;
+            }
+
+            for frame in &self.trace {
+                write!(f, "\n{:gutter$} = in {}", "", frame.name, gutter = gutter)?;
+                if let Some(span) = &frame.span {
+                    write!(f, " at {}", span)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::span::Source;
+
+    #[test]
+    fn renders_a_caret_under_the_failing_token() {
+        let source = Source::text("test.ps", b"/foo { 1 bar } def\nfoo\n");
+        let span = |line, column, len| {
+            Some(Span {
+                source: source.clone(),
+                line,
+                column,
+                len,
+            })
+        };
+
+        let trace = vec![Frame {
+            name: "foo".to_string(),
+            span: span(2, 1, 3),
+        }];
+        let error = Error::new(PsError::Undefined("bar".into()), span(1, 10, 3), trace);
+
+        let expected = "\
+/undefined in bar
+ --> test.ps:1:10
+  |
+1 | /foo { 1 bar } def
+  |          ^^^
+  = in foo at test.ps:2:1";
+        assert_eq!(expected, error.to_string());
+    }
+}
@@ -0,0 +1,111 @@
+use super::error::{Error, Frame};
+use super::file::File;
+use super::stack::{Array, Block, DictKey, Item};
+use super::PendingProc;
+
+/// An entry on the execution stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Exec {
+    /// Scanning and executing tokens from a file.
+    File {
+        file: File,
+        /// The last token taken from the file.
+        current: Option<Frame>,
+        /// Procedures in the file whose closing `}` hasn't been scanned yet, innermost last.
+        pending: Vec<PendingProc>,
+    },
+    /// Executing the items of a procedure, `pos` is the index of the next one.
+    Block { block: Block, pos: usize },
+    /// `repeat`: execute `proc` another `count` times.
+    Repeat { count: i32, proc: Block },
+    /// `for`: execute `proc` with `control` until it passes `limit`.
+    For {
+        control: Item,
+        increment: Item,
+        limit: Item,
+        proc: Block,
+    },
+    /// `forall` over an array: execute `proc` for each remaining item.
+    Forall {
+        items: Array,
+        pos: usize,
+        proc: Block,
+    },
+    /// `forall` over a dictionary: execute `proc` for each remaining entry.
+    ///
+    /// The entries are taken when the loop starts, so changing the dictionary doesn't affect it.
+    ForallDict {
+        entries: Vec<(DictKey, Item)>,
+        pos: usize,
+        proc: Block,
+    },
+    /// `loop`: execute `proc` until `exit` is called.
+    Loop { proc: Block },
+    /// `stopped`: catches `stop` and errors raised while executing `proc`.
+    Stopped { proc: Block },
+    /// Running the `errordict` entry `handler` for `error`, which is done once this is reached.
+    ///
+    /// The default handlers look for it to report `error` if there's no `stopped` to catch it.
+    Handler { handler: Item, error: Error },
+}
+
+impl Exec {
+    pub fn file(file: File) -> Self {
+        Exec::File {
+            file,
+            current: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn block(block: Block) -> Self {
+        Exec::Block { block, pos: 0 }
+    }
+
+    /// Whether `exit` terminates this entry.
+    pub fn is_loop(&self) -> bool {
+        matches!(
+            self,
+            Exec::Repeat { .. }
+                | Exec::For { .. }
+                | Exec::Forall { .. }
+                | Exec::ForallDict { .. }
+                | Exec::Loop { .. }
+        )
+    }
+
+    /// The item this entry executed last, which is where the entry above it was called from.
+    pub fn current(&self) -> Option<Frame> {
+        match self {
+            Exec::File { current, .. } => current.clone(),
+            Exec::Block { block, pos } => {
+                let index = pos.checked_sub(1)?;
+                let name = match block.get(index)? {
+                    Item::Name(name) => name.to_string(),
+                    Item::Operator(op) => op.name().to_string(),
+                    item => item.to_string(),
+                };
+                Some(Frame {
+                    name,
+                    span: block.spans.get(index).cloned(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The object representing this entry for `execstack`.
+    pub fn to_item(&self) -> Item {
+        match self {
+            Exec::File { file, .. } => Item::File(file.clone()),
+            Exec::Block { block, .. } => Item::Block(block.clone()),
+            Exec::Repeat { proc, .. }
+            | Exec::For { proc, .. }
+            | Exec::Forall { proc, .. }
+            | Exec::ForallDict { proc, .. }
+            | Exec::Loop { proc }
+            | Exec::Stopped { proc } => Item::Block(proc.clone()),
+            Exec::Handler { handler, .. } => handler.clone(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::cell::{RefCell, RefMut};
+use std::fmt;
+use std::io::Read;
+use std::rc::Rc;
+
+use super::scanner::Scanner;
+use super::span::Source;
+
+/// A file object. Copies share the same underlying reader and position.
+#[derive(Clone)]
+pub struct File {
+    scanner: Rc<RefCell<Scanner>>,
+}
+
+impl File {
+    pub fn new<R: Read + 'static>(reader: R, source: Source) -> Self {
+        Self::from_scanner(Scanner::new(reader, source))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self::from_scanner(Scanner::from_bytes(bytes))
+    }
+
+    fn from_scanner(scanner: Scanner) -> Self {
+        File {
+            scanner: Rc::new(RefCell::new(scanner)),
+        }
+    }
+
+    /// Borrow the scanner reading this file.
+    ///
+    /// The borrow must not be held while executing PostScript code,
+    /// as that code might read from the same file.
+    pub fn scanner(&self) -> RefMut<'_, Scanner> {
+        self.scanner.borrow_mut()
+    }
+}
+
+impl PartialEq for File {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.scanner, &other.scanner)
+    }
+}
+
+impl Eq for File {}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "File({:p})", Rc::as_ptr(&self.scanner))
+    }
+}
@@ -0,0 +1,1973 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+mod dsc;
+mod error;
+mod exec;
+mod file;
+mod name;
+pub mod operators;
+mod save;
+mod scanner;
+mod span;
+mod stack;
+mod strings;
+
+pub use dsc::DscComment;
+use error::Result;
+pub use error::{Error, Frame, PsError};
+use exec::Exec;
+pub use file::File;
+pub use name::Name;
+use save::Journal;
+use scanner::{Scanner, Token};
+pub use span::{Source, Span};
+pub use stack::{Access, Item, Stack};
+use stack::{Block, Composite, Dict, DictKey, Dictionary};
+
+macro_rules! bail {
+    ($kind:ident) => {
+        return Err(PsError::$kind(String::new()));
+    };
+    ($kind:ident, $($arg:tt)+) => {
+        return Err(PsError::$kind(format!($($arg)+)));
+    };
+}
+
+/// Default depth limit of the execution stack.
+pub const DEFAULT_EXEC_STACK_LIMIT: usize = 250;
+
+/// The number of permanent dictionaries at the bottom of the dictionary stack:
+/// `systemdict`, `globaldict` and `userdict`.
+const PERMANENT_DICTS: usize = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct State {
+    operand_stack: Stack<Item>,
+    /// The dictionary stack, starting with the permanent dictionaries.
+    dict_stack: Stack<Dict>,
+    dsc_comments: Vec<DscComment>,
+    exec_stack: Vec<Exec>,
+    exec_stack_limit: usize,
+    /// Handlers for errors by name, called with the command that failed.
+    errordict: Dict,
+    /// Information about the last error, as `$error`.
+    error_info: Dict,
+    /// The changes since each `save` that was not restored yet, innermost last.
+    saves: Vec<Journal>,
+    /// The number of `save` objects created so far, used to tell them apart.
+    save_count: usize,
+    /// The state of the random number generator behind `rand`, between 1 and 2^31 - 2.
+    rand_state: i32,
+    /// An error whose handler found no `stopped` to catch it, to be returned by `execute`.
+    uncaught: Option<Error>,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self {
+            operand_stack: Stack::new(),
+            dict_stack: Stack {
+                inner: vec![
+                    Rc::new(Dictionary::with_access(
+                        operators::operator_dict(operators::operators()),
+                        Access::ReadOnly,
+                    )),
+                    Dict::default(),
+                    Dict::default(),
+                ],
+            },
+            dsc_comments: Vec::new(),
+            exec_stack: Vec::new(),
+            exec_stack_limit: DEFAULT_EXEC_STACK_LIMIT,
+            errordict: Rc::new(Dictionary::new(operators::error_dict(
+                operators::operators(),
+            ))),
+            error_info: Rc::new(Dictionary::new(HashMap::from([(
+                "newerror".into(),
+                Item::Bool(false),
+            )]))),
+            saves: Vec::new(),
+            save_count: 0,
+            rand_state: 1,
+            uncaught: None,
+        }
+    }
+
+    /// Limit the depth of the execution stack. Exceeding it raises `/execstackoverflow`.
+    pub fn set_exec_stack_limit(&mut self, limit: usize) {
+        self.exec_stack_limit = limit;
+    }
+
+    /// Seed the random number generator behind `rand`, like `srand` does.
+    ///
+    /// The generator starts from the same seed in every new state, so output is
+    /// reproducible unless it is seeded differently.
+    pub fn set_rand_seed(&mut self, seed: i32) {
+        const MAX: i32 = 0x7fff_fffe;
+        self.rand_state = if seed < 1 {
+            -(seed % MAX) + 1
+        } else {
+            seed.min(MAX)
+        };
+    }
+
+    /// Advance the random number generator and return its next value.
+    ///
+    /// This is the Park-Miller "minimal standard" generator, which is what Ghostscript uses.
+    fn next_rand(&mut self) -> i32 {
+        const MODULUS: i64 = 0x7fff_ffff;
+        self.rand_state = (self.rand_state as i64 * 16807 % MODULUS) as i32;
+        self.rand_state
+    }
+
+    /// Push an entry onto the execution stack.
+    ///
+    /// A procedure whose last item is running is replaced, so recursive calls in tail
+    /// position don't grow the stack. It stays in place otherwise to locate errors.
+    fn push_exec(&mut self, exec: Exec) -> Result<()> {
+        if let Exec::Block { block, .. } = &exec {
+            block.items.check_execute()?;
+        }
+        if let Some(Exec::Block { block, pos }) = self.exec_stack.last() {
+            if *pos >= block.len() {
+                self.exec_stack.pop();
+            }
+        }
+        if self.exec_stack.len() >= self.exec_stack_limit {
+            bail!(Execstackoverflow);
+        }
+        self.exec_stack.push(exec);
+        Ok(())
+    }
+
+    /// Run the `errordict` handler for an error.
+    ///
+    /// Without a handler the error is recorded in `$error` and stops execution like `stop`.
+    /// Returns `false` if execution can't continue above `base`.
+    fn raise(&mut self, error: &PsError, base: usize) -> bool {
+        let name = error.name().to_string();
+        let command = match self.systemdict().borrow().get(&error.command().into()) {
+            Some(op @ Item::Operator(_)) => op.clone(),
+            _ => Item::Name(error.command().into()),
+        };
+
+        // Pushed without replacing a finished procedure, which is where the error happened.
+        let handler = self.errordict.borrow().get(&name.as_str().into()).cloned();
+        let room = self.exec_stack.len() + 2 <= self.exec_stack_limit;
+        if let Some(handler) = handler.filter(|handler| room && handler.is_executable()) {
+            let proc = match &handler {
+                Item::Block(block) => block.clone(),
+                item => Block::new(vec![item.clone()], Vec::new()),
+            };
+            let error = self.locate(error.clone());
+            self.exec_stack.push(Exec::Handler { handler, error });
+            self.exec_stack.push(Exec::block(proc));
+            self.operand_stack.push(command);
+            return true;
+        }
+
+        self.record_error(name, command, self.exec_stack.len());
+        self.stop(base)
+    }
+
+    /// Record an error in `$error`, along with the stacks and the first `depth` entries
+    /// of the execution stack.
+    fn record_error(&mut self, name: String, command: Item, depth: usize) {
+        let dstack = self
+            .dicts()
+            .map(|dict| Item::Dict(dict.clone()))
+            .collect::<Vec<_>>();
+        let estack = self.exec_stack[..depth]
+            .iter()
+            .map(Exec::to_item)
+            .collect::<Vec<_>>();
+        let info = [
+            ("newerror", Item::Bool(true)),
+            ("errorname", Item::Key(name.into())),
+            ("command", command),
+            ("ostack", self.operand_stack.inner.clone().into()),
+            ("estack", estack.into()),
+            ("dstack", dstack.into()),
+            ("recordstacks", Item::Bool(true)),
+        ];
+        self.journal(&Item::Dict(self.error_info.clone()));
+        self.error_info
+            .borrow_mut()
+            .extend(info.into_iter().map(|(key, value)| (key.into(), value)));
+    }
+
+    /// Unwind the execution stack to the innermost `stopped` above `base` and push `true`.
+    ///
+    /// Returns `false` if there is no such `stopped`.
+    fn stop(&mut self, base: usize) -> bool {
+        let stopped = self.exec_stack[base..]
+            .iter()
+            .rposition(|exec| matches!(exec, Exec::Stopped { .. }));
+        match stopped {
+            Some(index) => {
+                self.exec_stack.truncate(base + index);
+                self.operand_stack.push(Item::Bool(true));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Attribute an error without a command to the item executed last.
+    fn attribute(&self, error: PsError) -> PsError {
+        match self.exec_stack.iter().rev().find_map(Exec::current) {
+            Some(frame) => error.in_command(&frame.name),
+            None => error,
+        }
+    }
+
+    /// Locate an error at the item executed last and record the calls that led to it.
+    fn locate(&self, error: PsError) -> Error {
+        let mut frames = self.exec_stack.iter().rev().filter_map(Exec::current);
+        let span = frames.next().and_then(|frame| frame.span);
+        Error::new(error, span, frames.collect())
+    }
+
+    /// The operand stack.
+    pub fn operand_stack(&self) -> &Stack<Item> {
+        &self.operand_stack
+    }
+
+    /// Document structuring comments (`%%Keyword: value`) seen so far, in source order.
+    pub fn dsc_comments(&self) -> &[DscComment] {
+        &self.dsc_comments
+    }
+
+    /// The file currently being executed, as returned by `currentfile`.
+    fn current_file(&self) -> Option<&File> {
+        self.exec_stack.iter().rev().find_map(|exec| match exec {
+            Exec::File { file, .. } => Some(file),
+            _ => None,
+        })
+    }
+
+    /// The dictionaries on the dictionary stack, bottom first.
+    fn dicts(&self) -> impl DoubleEndedIterator<Item = &Dict> {
+        self.dict_stack.inner.iter()
+    }
+
+    /// The built-in operators, at the bottom of the dictionary stack.
+    fn systemdict(&self) -> &Dict {
+        &self.dict_stack.inner[0]
+    }
+
+    /// The topmost dictionary on the dictionary stack defining `key`.
+    fn find_dict(&self, key: &DictKey) -> Option<&Dict> {
+        self.dicts()
+            .rev()
+            .find(|dict| dict.borrow().contains_key(key))
+    }
+
+    /// The dictionary for definitions, at the top of the dictionary stack.
+    fn current_dict(&self) -> &Dict {
+        self.dict_stack.inner.last().unwrap()
+    }
+
+    /// Pop the current dictionary.
+    ///
+    /// Raises `/dictstackunderflow` rather than popping a permanent dictionary.
+    fn end(&mut self) -> Result<()> {
+        if self.dict_stack.len() <= PERMANENT_DICTS {
+            bail!(Dictstackunderflow);
+        }
+        self.dict_stack.pop()?;
+        Ok(())
+    }
+
+    /// Pop all dictionaries above the permanent ones.
+    fn clear_dicts(&mut self) {
+        self.dict_stack.inner.truncate(PERMANENT_DICTS);
+    }
+
+    /// Start recording changes to composite objects and return the id of the `save` object.
+    fn save(&mut self) -> usize {
+        self.save_count += 1;
+        self.saves.push(Journal::new(self.save_count));
+        self.save_count
+    }
+
+    /// Record the contents of a composite object before changing them, so `restore` can
+    /// put them back.
+    fn journal(&mut self, item: &Item) {
+        if let Some(journal) = self.saves.last_mut() {
+            journal.record(item);
+        }
+    }
+
+    /// Undo all changes to composite objects made since the `save` with id `id`.
+    ///
+    /// Raises `/invalidrestore` if that `save` was already restored, or if any stack
+    /// still holds a composite object created after it.
+    fn restore(&mut self, id: usize) -> Result<()> {
+        let Some(index) = self.saves.iter().position(|journal| journal.id() == id) else {
+            bail!(Invalidrestore);
+        };
+        let journal = &self.saves[index];
+        let newer = self
+            .operand_stack
+            .inner
+            .iter()
+            .cloned()
+            .chain(self.dicts().map(|dict| Item::Dict(dict.clone())))
+            .chain(self.exec_stack.iter().flat_map(save::exec_items))
+            .any(|item| journal.is_newer(&item));
+        if newer {
+            bail!(Invalidrestore);
+        }
+
+        // Innermost first, as each journal holds the contents from the time of its `save`.
+        for journal in self.saves.drain(index..).rev() {
+            journal.restore();
+        }
+        Ok(())
+    }
+
+    /// Look up `key` in the dictionary stack, from the top down to `systemdict`.
+    fn get(&self, key: impl Into<DictKey>) -> Option<Item> {
+        let key = key.into();
+        self.dicts()
+            .rev()
+            .find_map(|dict| dict.borrow().get(&key).cloned())
+    }
+}
+
+/// Whether `code` ends inside a procedure, so an interactive session should read more
+/// lines before executing it. Procedures can't span several calls to `execute`.
+pub fn is_incomplete(code: &str) -> bool {
+    let mut scanner = Scanner::from_bytes(code.as_bytes().to_vec());
+    let mut depth = 0;
+    while let Ok(Some(token)) = scanner.next_token() {
+        match token {
+            Token::ProcBegin => depth += 1,
+            Token::ProcEnd if depth > 0 => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+/// Execute PostScript source code.
+pub fn execute(code: &str, state: &mut State) -> Result<(), Error> {
+    execute_file(File::from_bytes(code.as_bytes().to_vec()), state)
+}
+
+/// Execute PostScript source code, pulling tokens from `reader` as they are needed.
+pub fn execute_reader<R: Read + 'static>(reader: R, state: &mut State) -> Result<(), Error> {
+    let file = File::new(reader, Source::stream("<stream>"));
+    execute_file(file, state)
+}
+
+/// Execute the PostScript file at `path`, reading it as tokens are needed.
+pub fn execute_path(path: &Path, state: &mut State) -> Result<(), Error> {
+    let reader = fs::File::open(path).map_err(|_| {
+        let error = PsError::Undefinedfilename(path.display().to_string());
+        Error::new(error, None, vec![])
+    })?;
+    let file = File::new(reader, Source::path(path));
+    execute_file(file, state)
+}
+
+fn execute_file(file: File, state: &mut State) -> Result<(), Error> {
+    // Strings executed or scanned while running the document aren't part of it.
+    file.scanner().collect_dsc_comments();
+    let base = state.exec_stack.len();
+    if let Err(e) = state.push_exec(Exec::file(file)) {
+        return Err(state.locate(e));
+    }
+
+    while state.exec_stack.len() > base {
+        let result = step(state);
+        if let Some(e) = state.uncaught.take() {
+            state.exec_stack.truncate(base);
+            return Err(e);
+        }
+        if let Err(e) = result {
+            let e = state.attribute(e);
+            if state.raise(&e, base) {
+                continue;
+            }
+
+            let e = state.locate(e);
+            state.exec_stack.truncate(base);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Execute the next item of the entry on top of the execution stack.
+fn step(state: &mut State) -> Result<()> {
+    let top = state.exec_stack.len() - 1;
+    match &mut state.exec_stack[top] {
+        Exec::File { file, pending, .. } => {
+            let file = file.clone();
+            // Taken out while scanning and only put back if that succeeds, so an error
+            // drops procedures left open by it.
+            let mut pending = mem::take(pending);
+            let next = next_token(&file, state);
+            let (token, span) = match next {
+                Ok(Some(token)) => token,
+                Ok(None) if pending.is_empty() => {
+                    state.exec_stack.pop();
+                    return Ok(());
+                }
+                Ok(None) => {
+                    let span = file.scanner().last_span();
+                    set_current(state, top, None, span);
+                    bail!(Syntaxerror, "{{");
+                }
+                Err(e) => {
+                    let span = file.scanner().last_span();
+                    set_current(state, top, None, span);
+                    return Err(e);
+                }
+            };
+
+            let assembled = assemble(token, span.clone(), &mut pending, state);
+            if assembled.is_ok() {
+                if let Exec::File { pending: slot, .. } = &mut state.exec_stack[top] {
+                    *slot = pending;
+                }
+            }
+
+            let (item, span) = match assembled {
+                Ok(Some(item)) => item,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    set_current(state, top, None, span);
+                    return Err(e);
+                }
+            };
+            let name = match &item {
+                Item::Name(name) => Some(name),
+                _ => None,
+            };
+            set_current(state, top, name, span);
+            execute_item(item, state)
+        }
+        Exec::Block { block, pos } => {
+            let Some(item) = block.get(*pos) else {
+                state.exec_stack.pop();
+                return Ok(());
+            };
+
+            *pos += 1;
+            execute_item(item, state)
+        }
+        Exec::Repeat { count, proc } => {
+            if *count <= 0 {
+                state.exec_stack.pop();
+                return Ok(());
+            }
+
+            *count -= 1;
+            let proc = proc.clone();
+            state.push_exec(Exec::block(proc))
+        }
+        Exec::For {
+            control,
+            increment,
+            limit,
+            proc,
+        } => {
+            fn passed<T: PartialOrd>(value: T, limit: T, ascending: bool) -> bool {
+                if ascending {
+                    value > limit
+                } else {
+                    value < limit
+                }
+            }
+            fn real(item: &Item) -> Result<f64> {
+                match *item {
+                    Item::Number(n) => Ok(n.into()),
+                    ref item => Ok(item.as_float()?.into()),
+                }
+            }
+
+            // Integers are compared exactly, as reals can't tell large ones apart.
+            let ascending = increment.as_float()? >= 0.0;
+            let done = match (&*control, &*limit) {
+                (&Item::Number(value), &Item::Number(limit)) => passed(value, limit, ascending),
+                (control, limit) => passed(real(control)?, real(limit)?, ascending),
+            };
+            if done {
+                state.exec_stack.pop();
+                return Ok(());
+            }
+
+            // An integer control that can't be incremented has reached the end of the
+            // integer range, so this is the last iteration.
+            let next = match (&*control, &*increment) {
+                (&Item::Number(a), &Item::Number(b)) => a.checked_add(b).map(Item::Number),
+                (control, increment) => {
+                    Some(Item::Float(control.as_float()? + increment.as_float()?))
+                }
+            };
+            let last = next.is_none();
+            let current = match next {
+                Some(next) => mem::replace(control, next),
+                None => control.clone(),
+            };
+            let proc = proc.clone();
+            if last {
+                state.exec_stack.pop();
+            }
+            state.operand_stack.push(current);
+            state.push_exec(Exec::block(proc))
+        }
+        Exec::Forall { items, pos, proc } => {
+            let Some(item) = items.borrow().get(*pos).cloned() else {
+                state.exec_stack.pop();
+                return Ok(());
+            };
+
+            *pos += 1;
+            let proc = proc.clone();
+            state.operand_stack.push(item);
+            state.push_exec(Exec::block(proc))
+        }
+        Exec::ForallDict { entries, pos, proc } => {
+            let Some((key, value)) = entries.get(*pos).cloned() else {
+                state.exec_stack.pop();
+                return Ok(());
+            };
+
+            *pos += 1;
+            let proc = proc.clone();
+            state.operand_stack.push(key.into_item());
+            state.operand_stack.push(value);
+            state.push_exec(Exec::block(proc))
+        }
+        Exec::Loop { proc } => {
+            let proc = proc.clone();
+            state.push_exec(Exec::block(proc))
+        }
+        Exec::Stopped { .. } => {
+            state.exec_stack.pop();
+            state.operand_stack.push(Item::Bool(false));
+            Ok(())
+        }
+        Exec::Handler { .. } => {
+            state.exec_stack.pop();
+            Ok(())
+        }
+    }
+}
+
+fn set_current(state: &mut State, index: usize, name: Option<&Name>, span: Span) {
+    if let Exec::File { current, .. } = &mut state.exec_stack[index] {
+        *current = Some(Frame {
+            name: name.map(Name::to_string).unwrap_or_default(),
+            span: Some(span),
+        });
+    }
+}
+
+/// Execute an item encountered directly in a file or block.
+///
+/// Names are looked up and executed, executable strings and files are run and
+/// everything else, including blocks, is pushed.
+fn execute_item(item: Item, state: &mut State) -> Result<()> {
+    match item {
+        Item::Name(name) => execute_name(name, state),
+        Item::Operator(op) => op.call(state),
+        item @ Item::Executable(_) => execute_object(item, state),
+        item => {
+            state.operand_stack.push(item);
+            Ok(())
+        }
+    }
+}
+
+/// Execute an object the way `exec` does.
+///
+/// Executable objects are run, literal objects are pushed.
+pub(crate) fn execute_object(item: Item, state: &mut State) -> Result<()> {
+    match item {
+        Item::Name(name) => execute_name(name, state),
+        Item::Block(block) => state.push_exec(Exec::block(block)),
+        Item::Operator(op) => op.call(state),
+        Item::Executable(item) => match *item {
+            Item::String(s) => {
+                s.check_execute()?;
+                state.push_exec(Exec::file(File::from_bytes(s.borrow().clone())))
+            }
+            Item::File(file) => state.push_exec(Exec::file(file)),
+            item => {
+                state.operand_stack.push(item.into_executable());
+                Ok(())
+            }
+        },
+        item => {
+            state.operand_stack.push(item);
+            Ok(())
+        }
+    }
+}
+
+fn next_token(file: &File, state: &mut State) -> Result<Option<(Token, Span)>> {
+    let mut scanner = file.scanner();
+    let token = scanner.next_token();
+    state.dsc_comments.extend(scanner.take_dsc_comments());
+    let span = scanner.last_span();
+    Ok(token?.map(|token| (token, span)))
+}
+
+/// A procedure whose closing `}` hasn't been scanned yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PendingProc {
+    items: Vec<Item>,
+    spans: Vec<Span>,
+    start: Option<Span>,
+}
+
+/// Turn a token into an item, collecting procedures in `pending` until they are complete.
+///
+/// Returns the item and its location once it is complete and outside of any procedure.
+fn assemble(
+    token: Token,
+    span: Span,
+    pending: &mut Vec<PendingProc>,
+    state: &State,
+) -> Result<Option<(Item, Span)>> {
+    let (item, span) = match token {
+        Token::ProcBegin => {
+            pending.push(PendingProc {
+                start: Some(span),
+                ..PendingProc::default()
+            });
+            return Ok(None);
+        }
+        Token::ProcEnd => match pending.pop() {
+            Some(proc) => {
+                let block = Block::new(proc.items, proc.spans);
+                (Item::Block(block), proc.start.unwrap_or(span))
+            }
+            None => {
+                bail!(Syntaxerror, "}}");
+            }
+        },
+        Token::Integer(n) => (Item::Number(n), span),
+        Token::Real(n) => (Item::Float(n), span),
+        Token::String(s) => (Item::string(s), span),
+        Token::LiteralName(name) => (Item::Key(name), span),
+        Token::Name(name) => (Item::Name(name), span),
+        Token::ImmediateName(name) => match state.get(name.clone()) {
+            Some(item) => (item.clone(), span),
+            None => {
+                bail!(Undefined, "{}", name);
+            }
+        },
+    };
+
+    match pending.last_mut() {
+        Some(proc) => {
+            proc.items.push(item);
+            proc.spans.push(span);
+            Ok(None)
+        }
+        None => Ok(Some((item, span))),
+    }
+}
+
+/// Look up an executable name and execute its value.
+pub(crate) fn execute_name(mut name: Name, state: &mut State) -> Result<()> {
+    // Names referring to names are followed here, counting them against the execution
+    // stack limit to catch cycles.
+    for _ in 0..state.exec_stack_limit {
+        match state.get(name.clone()) {
+            Some(Item::Name(next)) => name = next,
+            Some(item) => return execute_object(item, state),
+            None => {
+                bail!(Undefined, "{}", name);
+            }
+        }
+    }
+    bail!(Execstackoverflow);
+}
+
+/// Scan the next complete object from `file`, reading the whole procedure for `{`.
+///
+/// Used by operators consuming source code, like `token`.
+pub(crate) fn scan_object(file: &File, state: &mut State) -> Result<Option<Item>> {
+    let mut pending = vec![];
+    loop {
+        let (token, span) = match next_token(file, state)? {
+            Some(token) => token,
+            None if pending.is_empty() => return Ok(None),
+            None => {
+                bail!(Syntaxerror, "{{");
+            }
+        };
+        if let Some((item, _)) = assemble(token, span, &mut pending, state)? {
+            return Ok(Some(item));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_runs() {
+        let mut state = State::new();
+
+        let code = "1 1 add";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(2));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn procs_only_run_on_exec() {
+        let mut state = State::new();
+
+        let code = "{ 1 1 add }";
+        execute(code, &mut state).unwrap();
+
+        let top = state.operand_stack.pop().unwrap();
+        assert!(matches!(top, Item::Block(_)));
+        assert_eq!(0, state.operand_stack.len());
+
+        let code = "{ 1 1 add } exec";
+        execute(code, &mut state).unwrap();
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(2));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn procs_do_nest() {
+        let mut state = State::new();
+
+        let code = "{ 1 1 { add } exec }";
+        execute(code, &mut state).unwrap();
+
+        let top = state.operand_stack.pop().unwrap();
+        assert!(matches!(top, Item::Block(_)));
+        assert_eq!(0, state.operand_stack.len());
+    }
+
+    #[test]
+    fn procs_do_nest_and_run() {
+        let mut state = State::new();
+
+        let code = "{ 1 1 { add } exec } exec";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(2));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn parses_float() {
+        let mut state = State::new();
+
+        let code = "1.5 +0.5 -0.7";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Float(1.5));
+        expected.operand_stack.push(Item::Float(0.5));
+        expected.operand_stack.push(Item::Float(-0.7));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn parses_with_radix() {
+        let mut state = State::new();
+
+        let code = "16#FF 4#3";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(255));
+        expected.operand_stack.push(Item::Number(3));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn parses_strings() {
+        let mut state = State::new();
+
+        let code = r"(Hello) (a (nested) \(string\)) (one\ntwo\101)";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::string(b"Hello".to_vec()));
+        expected
+            .operand_stack
+            .push(Item::string(b"a (nested) (string)".to_vec()));
+        expected
+            .operand_stack
+            .push(Item::string(b"one\ntwoA".to_vec()));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn strings_in_procs_keep_their_content() {
+        let mut state = State::new();
+
+        let code = "{ (a  b) ( } ) } exec";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::string(b"a  b".to_vec()));
+        expected.operand_stack.push(Item::string(b" } ".to_vec()));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn unterminated_string_is_a_syntaxerror() {
+        let mut state = State::new();
+
+        let err = execute("(abc", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/syntaxerror"));
+    }
+
+    #[test]
+    fn parses_hex_and_base85_strings() {
+        let mut state = State::new();
+
+        let code = "<48 65 6c6c6f> <~87cURDZ~> <> { <41> } exec";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::string(b"Hello".to_vec()));
+        expected.operand_stack.push(Item::string(b"Hello".to_vec()));
+        expected.operand_stack.push(Item::string(vec![]));
+        expected.operand_stack.push(Item::string(b"A".to_vec()));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn bad_hex_and_base85_strings_are_syntaxerrors() {
+        for code in ["<4x>", "<~87v~>", "<41", "<~87cU"] {
+            let mut state = State::new();
+            let err = execute(code, &mut state).unwrap_err();
+            assert!(err.to_string().starts_with("/syntaxerror"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let mut state = State::new();
+
+        let code = "%!PS-Adobe-3.0\n1 % 2\n(%not a comment) 3 %end";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(1));
+        expected
+            .operand_stack
+            .push(Item::string(b"%not a comment".to_vec()));
+        expected.operand_stack.push(Item::Number(3));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn dsc_comments_are_collected() {
+        let mut state = State::new();
+
+        let code = "%!PS-Adobe-3.0\n%%BoundingBox: 0 0 612 792\n%%EndComments\n1 %%Page: 1 1\n%%Page: 1 1\n2";
+        execute(code, &mut state).unwrap();
+
+        let comments = state.dsc_comments();
+        assert_eq!(3, comments.len());
+        assert_eq!(2, comments[0].line);
+        assert_eq!("BoundingBox", comments[0].keyword);
+        assert_eq!(Some("0 0 612 792"), comments[0].value.as_deref());
+        assert_eq!(3, comments[1].line);
+        assert_eq!("EndComments", comments[1].keyword);
+        assert_eq!(None, comments[1].value);
+        assert_eq!(5, comments[2].line);
+        assert_eq!("Page", comments[2].keyword);
+        assert_eq!(2, state.operand_stack().len());
+    }
+
+    #[test]
+    fn dsc_comments_are_only_collected_from_the_document() {
+        let mut state = State::new();
+
+        let code = "%%Title: doc\n(%%Page: 9 9\n1) cvx exec (%%Title: x\n) token";
+        execute(code, &mut state).unwrap();
+
+        let comments = state.dsc_comments();
+        assert_eq!(1, comments.len());
+        assert_eq!(Some("doc"), comments[0].value.as_deref());
+    }
+
+    #[test]
+    fn parses_names_with_special_characters() {
+        let mut state = State::new();
+
+        let code = "/x1 1 def /$error 2 def /@foo 3 def /a*b 4 def /1a 5 def /- 6 def
+                    x1 $error @foo a*b 1a -";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for i in 1..=6 {
+            expected.operand_stack.push(Item::Number(i));
+        }
+        expected.dict_stack.inner = state.dict_stack.inner.clone();
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn names_end_at_delimiters() {
+        let mut state = State::new();
+
+        let code = "/a/b(c)/ [/d]";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push("a".to_string().into());
+        expected.operand_stack.push("b".to_string().into());
+        expected.operand_stack.push(Item::string(b"c".to_vec()));
+        expected.operand_stack.push("".to_string().into());
+        expected
+            .operand_stack
+            .push(vec!["d".to_string().into()].into());
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn immediate_names_in_procs_are_replaced_when_scanned() {
+        let mut state = State::new();
+
+        let code = "/x 1 def /p { //x x } def /x 2 def p";
+        execute(code, &mut state).unwrap();
+
+        assert_eq!(
+            vec![Item::Number(1), Item::Number(2)],
+            state.operand_stack.inner
+        );
+    }
+
+    #[test]
+    fn procs_are_scanned_into_items() {
+        let mut state = State::new();
+
+        execute("{ 1 /a (s) { add } }", &mut state).unwrap();
+
+        let inner = Item::Block(vec![Item::Name("add".into())].into());
+        let expected = Item::Block(
+            vec![
+                Item::Number(1),
+                Item::Key("a".into()),
+                Item::string(b"s".to_vec()),
+                inner,
+            ]
+            .into(),
+        );
+        assert_eq!(vec![expected], state.operand_stack.inner);
+    }
+
+    #[test]
+    fn procs_cannot_span_multiple_executions() {
+        let mut state = State::new();
+
+        assert!(is_incomplete("/f { 1"));
+        execute("/f { 1", &mut state).unwrap_err();
+
+        let mut state = State::new();
+        assert!(!is_incomplete("/f { 1\nadd } def 2 f"));
+        execute("/f { 1\nadd } def 2 f", &mut state).unwrap();
+
+        assert_eq!(vec![Item::Number(3)], state.operand_stack.inner);
+    }
+
+    #[test]
+    fn immediate_names_are_looked_up() {
+        let mut state = State::new();
+
+        let code = "/x 7 def //x { //x } exec";
+        execute(code, &mut state).unwrap();
+
+        assert_eq!(
+            vec![Item::Number(7), Item::Number(7)],
+            state.operand_stack.inner
+        );
+
+        let err = execute("{ //nope }", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/undefined"));
+    }
+
+    #[test]
+    fn parses_all_float_forms() {
+        let mut state = State::new();
+
+        let code = ".5 5. -.5 1e10 -3.2E-4 1.0e+2 6E2";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [0.5, 5.0, -0.5, 1e10, -3.2e-4, 100.0, 600.0] {
+            expected.operand_stack.push(Item::Float(n));
+        }
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn integer_overflow_turns_into_real() {
+        let mut state = State::new();
+
+        let code = "2147483647 2147483648 -2147483649";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(i32::MAX));
+        expected.operand_stack.push(Item::Float(2147483648.0));
+        expected.operand_stack.push(Item::Float(-2147483649.0));
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn deeply_nested_procedures_are_a_limitcheck() {
+        let code = "{".repeat(200_000) + &"}".repeat(200_000);
+        let err = execute(&code, &mut State::new()).unwrap_err();
+        assert!(err.to_string().starts_with("/limitcheck"));
+    }
+
+    #[test]
+    fn radix_numbers_are_32_bit() {
+        let mut state = State::new();
+
+        execute("16#FFFFFFFF 2#1000 { 8#17 } exec", &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(-1));
+        expected.operand_stack.push(Item::Number(8));
+        expected.operand_stack.push(Item::Number(15));
+        assert_eq!(expected, state);
+
+        let err = execute("16#100000000", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/limitcheck"));
+        let err = execute("8#9", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/undefined"));
+    }
+
+    #[test]
+    fn accepts_all_whitespace_characters() {
+        let mut state = State::new();
+
+        let code = "1\t2\r\n3\r4\x0c5\x006 % comment\r\n7";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for i in 1..=7 {
+            expected.operand_stack.push(Item::Number(i));
+        }
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn delimiters_separate_tokens() {
+        let mut state = State::new();
+
+        let code = "/a{1 2 add}def a[3 4]length(x)(y)<41>/b";
+        execute(code, &mut state).unwrap();
+
+        assert_eq!(
+            vec![
+                Item::Number(3),
+                Item::Number(2),
+                Item::string(b"x".to_vec()),
+                Item::string(b"y".to_vec()),
+                Item::string(b"A".to_vec()),
+                "b".to_string().into(),
+            ],
+            state.operand_stack.inner
+        );
+
+        // `2add` is not a number followed by a name but a single name.
+        let err = execute("1 2add", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/undefined in 2add"));
+    }
+
+    #[test]
+    fn dsc_comments_with_crlf_line_endings() {
+        let mut state = State::new();
+
+        let code = "%!PS\r\n%%Pages: 1\r\n%%EndComments\r\n";
+        execute(code, &mut state).unwrap();
+
+        let comments = state.dsc_comments();
+        assert_eq!(2, comments.len());
+        assert_eq!(Some("1"), comments[0].value.as_deref());
+        assert_eq!(3, comments[1].line);
+    }
+
+    #[test]
+    fn errors_report_their_location() {
+        let mut state = State::new();
+
+        let code = "1 2 add\n  3 foo 4";
+        let err = execute(code, &mut state).unwrap_err();
+
+        assert_eq!(&PsError::Undefined("foo".into()), err.error());
+        let span = err.span().unwrap();
+        assert_eq!((2, 5, 3), (span.line, span.column, span.len));
+        assert!(err.trace().is_empty());
+    }
+
+    #[test]
+    fn errors_in_procs_report_the_call_chain() {
+        let mut state = State::new();
+
+        let code = "/inner { 1 (a) add } def\n/outer { inner 0 } def\n{ outer 0 } exec";
+        let err = execute(code, &mut state).unwrap_err();
+
+        let span = err.span().unwrap();
+        assert_eq!((1, 16, 3), (span.line, span.column, span.len));
+
+        let trace = err
+            .trace()
+            .iter()
+            .map(|frame| {
+                let span = frame.span.as_ref().unwrap();
+                (frame.name.as_str(), span.line, span.column)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("inner", 2, 10), ("outer", 3, 3), ("exec", 3, 13)],
+            trace
+        );
+    }
+
+    #[test]
+    fn syntaxerrors_report_their_location() {
+        let mut state = State::new();
+
+        let err = execute("1 2\n  <4x>", &mut state).unwrap_err();
+
+        assert!(matches!(err.error(), PsError::Syntaxerror(_)));
+        let span = err.span().unwrap();
+        assert_eq!((2, 3), (span.line, span.column));
+    }
+
+    #[test]
+    fn deep_recursion_overflows_the_exec_stack() {
+        let mut state = State::new();
+
+        let err = execute("/f { f 1 } def f", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/execstackoverflow"));
+        assert!(state.exec_stack.is_empty());
+    }
+
+    #[test]
+    fn exec_stack_limit_is_configurable() {
+        let mut state = State::new();
+        state.set_exec_stack_limit(5);
+
+        execute("{ { { 1 } exec 2 } exec 3 } exec", &mut state).unwrap();
+        let err = execute(
+            "{ { { { { 1 } exec 2 } exec 3 } exec 4 } exec 5 } exec",
+            &mut state,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("/execstackoverflow"));
+    }
+
+    #[test]
+    fn loops_run_on_the_exec_stack() {
+        let mut state = State::new();
+
+        let code = "0 100000 { 1 add } repeat 1 2 7 { } for 1 -0.5 0 { } for [4 5] { } forall";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            Item::Number(100000),
+            Item::Number(1),
+            Item::Number(3),
+            Item::Number(5),
+            Item::Number(7),
+            Item::Float(1.0),
+            Item::Float(0.5),
+            Item::Float(0.0),
+            Item::Number(4),
+            Item::Number(5),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn integer_for_loops_stop_at_the_ends_of_the_integer_range() {
+        let mut state = State::new();
+
+        let code = "0 2147483640 1 2147483647 { pop 1 add } for \
+                    0 -2147483641 -1 -2147483648 { pop 1 add } for \
+                    2147483647 dup 1 exch { } for";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [8, 8, i32::MAX] {
+            expected.operand_stack.push(Item::Number(n));
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn exec_stack_can_be_inspected() {
+        let mut state = State::new();
+
+        execute("{ countexecstack [0 0 0 0] execstack } exec", &mut state).unwrap();
+
+        let array = state.operand_stack.pop().unwrap();
+        let array = array.as_array().unwrap().borrow();
+        assert_eq!(2, array.len());
+        assert!(matches!(array[0], Item::File(_)));
+        assert!(matches!(array[1], Item::Block(_)));
+        assert_eq!(Item::Number(2), state.operand_stack.pop().unwrap());
+    }
+
+    #[test]
+    fn exit_terminates_the_innermost_loop() {
+        let mut state = State::new();
+
+        let code = "0 { 1 add dup 5 eq { exit } if } loop \
+                    10 { 1 add exit } repeat \
+                    0 1 10 { dup 2 eq { exit } if } for \
+                    [7 8 9] { exit } forall";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [6, 0, 1, 2, 7] {
+            expected.operand_stack.push(Item::Number(item));
+        }
+        assert_eq!(expected, state);
+    }
+
+    #[test]
+    fn exit_outside_a_loop_is_invalid() {
+        let mut state = State::new();
+
+        let err = execute("{ exit } exec", &mut state).unwrap_err();
+        assert_eq!(&PsError::Invalidexit("exit".into()), err.error());
+
+        let mut state = State::new();
+        execute("{ { exit } stopped exit } loop", &mut state).unwrap();
+        assert_eq!(Item::Bool(true), state.operand_stack.pop().unwrap());
+    }
+
+    #[test]
+    fn stopped_catches_stop_and_errors() {
+        let mut state = State::new();
+
+        let code = "{ 1 2 add } stopped \
+                    { 4 stop 5 } stopped \
+                    { 1 { undefinedname } repeat } stopped";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            Item::Number(3),
+            Item::Bool(false),
+            Item::Number(4),
+            Item::Bool(true),
+            Item::Bool(true),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        let err = execute("stop", &mut state).unwrap_err();
+        assert_eq!(&PsError::Invalidstop("stop".into()), err.error());
+    }
+
+    #[test]
+    fn failing_operators_leave_their_operands() {
+        let mut state = State::new();
+
+        execute("{ 1 (a) add } stopped pop $error /ostack get", &mut state).unwrap();
+        let ostack = state.operand_stack.pop().unwrap();
+        assert_eq!("[1 (a)]", ostack.to_string());
+        assert_eq!("(a)", state.operand_stack.pop().unwrap().to_string());
+        assert_eq!(Item::Number(1), state.operand_stack.pop().unwrap());
+    }
+
+    #[test]
+    fn errors_are_recorded_in_dollar_error() {
+        let mut state = State::new();
+
+        execute("{ 1 (a) add } stopped", &mut state).unwrap();
+        assert_eq!(Item::Bool(true), state.operand_stack.pop().unwrap());
+        assert_eq!("(a)", state.operand_stack.pop().unwrap().to_string());
+        assert_eq!(Item::Number(1), state.operand_stack.pop().unwrap());
+
+        let info = state.error_info.borrow();
+        assert_eq!(Some(&Item::Bool(true)), info.get(&"newerror".into()));
+        assert_eq!(
+            Some(&Item::Key("typecheck".into())),
+            info.get(&"errorname".into())
+        );
+        let command = info.get(&"command".into()).map(Item::to_string);
+        assert_eq!(Some("--add--".to_string()), command);
+        assert!(
+            matches!(info.get(&"estack".into()), Some(Item::Array(items)) if items.borrow().len() == 3)
+        );
+        drop(info);
+
+        execute("handleerror", &mut state).unwrap();
+        let info = state.error_info.borrow();
+        assert_eq!(Some(&Item::Bool(false)), info.get(&"newerror".into()));
+    }
+
+    #[test]
+    fn errordict_handlers_replace_the_default() {
+        let mut state = State::new();
+
+        let code = "errordict begin /undefined { pop 0 } def end 1 missing 2";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [1, 0, 2] {
+            expected.operand_stack.push(Item::Number(item));
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn errordict_has_default_handlers_that_can_be_wrapped() {
+        let mut state = State::new();
+
+        let code = "errordict /typecheck known errordict /handleerror known \
+                    /old errordict /undefined get def \
+                    errordict /undefined { /wrapped true def old } put \
+                    { missing } stopped wrapped $error /errorname get";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            true.into(),
+            true.into(),
+            true.into(),
+            true.into(),
+            Item::Key("undefined".into()),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        let err = execute("1 missing", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/undefined in missing"));
+        assert_eq!(Some(3), err.span().map(|span| span.column));
+        let info = state.error_info.borrow();
+        assert_eq!(Some(&Item::Bool(true)), info.get(&"newerror".into()));
+    }
+
+    #[test]
+    fn huge_dictionaries_grow_as_needed() {
+        let mut state = State::new();
+
+        execute("2000000000 dict dup /a 1 put length", &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(1.into());
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn errors_name_the_offending_operator() {
+        for (code, expected) in [
+            ("1 (a) add", PsError::Typecheck("add".into())),
+            ("pop", PsError::Stackunderflow("pop".into())),
+            ("1 2 if", PsError::Typecheck("if".into())),
+            ("(a) begin", PsError::Typecheck("begin".into())),
+            ("[1] { } 3 forall", PsError::Typecheck("forall".into())),
+            ("-1 dict", PsError::Rangecheck("dict".into())),
+            ("2000000000 string", PsError::Limitcheck("string".into())),
+            ("1e30 cvi", PsError::Rangecheck("cvi".into())),
+            ("{ 1 ] } exec", PsError::Unmatchedmark("]".into())),
+        ] {
+            let mut state = State::new();
+            let err = execute(code, &mut state).unwrap_err();
+            assert_eq!(&expected, err.error(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn executable_strings_and_names_are_run() {
+        let mut state = State::new();
+
+        let code = "(1 2 add) cvx exec \
+                    /s (3 mul) cvx def s \
+                    /t /s cvx def t \
+                    /l [4 5] def l length \
+                    (6) exec";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(27));
+        expected.operand_stack.push(Item::Number(2));
+        expected.operand_stack.push(Item::string(b"6".to_vec()));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        let err = execute("/a /a cvx def a", &mut state).unwrap_err();
+        assert!(matches!(err.error(), PsError::Execstackoverflow(_)));
+    }
+
+    #[test]
+    fn operators_are_objects() {
+        let mut state = State::new();
+
+        execute("1 2 /add load exec /add load", &mut state).unwrap();
+
+        let add = state.operand_stack.pop().unwrap();
+        assert_eq!("--add--", add.to_string());
+        assert!(add.is_executable());
+        assert_eq!(Item::Number(3), state.operand_stack.pop().unwrap());
+
+        let err = execute("/nothing load", &mut state).unwrap_err();
+        assert_eq!(&PsError::Undefined("nothing".into()), err.error());
+    }
+
+    #[test]
+    fn bind_replaces_operator_names_recursively() {
+        let mut state = State::new();
+
+        let code = "/f { 1 2 add { dup mul } exec g } bind def \
+                    /add { sub } def /g 0 def f";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(9));
+        expected.operand_stack.push(Item::Number(0));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        let f = state.get("f").unwrap().to_string();
+        assert_eq!("{1 2 --add-- {--dup-- --mul--} --exec-- g}", f);
+    }
+
+    #[test]
+    fn composite_objects_are_shared() {
+        let mut state = State::new();
+
+        let code = "/a [1 2] def a dup 0 9 put a 0 get \
+                    /s (ab) def s 1 99 put s \
+                    /p { 1 } def /p load 0 2 put p \
+                    /d 1 dict def d begin /x 5 def end d begin x end";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected
+            .operand_stack
+            .push(Item::from(vec![9.into(), 2.into()]));
+        expected.operand_stack.push(Item::Number(9));
+        expected.operand_stack.push(Item::string(b"ac".to_vec()));
+        expected.operand_stack.push(Item::Number(2));
+        expected.operand_stack.push(Item::Number(5));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn eq_compares_composite_objects_by_identity() {
+        let mut state = State::new();
+
+        let code = "[1] [1] eq /a [1] def a a eq a a cvx eq \
+                    (ab) (ab) eq (ab) /ab eq 1 1.0 eq \
+                    1 dict 1 dict eq";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for b in [false, true, true, true, true, true, false] {
+            expected.operand_stack.push(b.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn objects_containing_themselves_can_be_printed() {
+        let mut state = State::new();
+
+        let code = "/a [0 1] def a 0 a put a /p { 0 } def /p load 0 /p load put /p load";
+        execute(code, &mut state).unwrap();
+
+        let proc = state.operand_stack.pop().unwrap();
+        assert_eq!("{{...}}", proc.to_string());
+        let array = state.operand_stack.pop().unwrap();
+        assert_eq!("[[...] 1]", array.to_string());
+        assert!(format!("{:?}", array).contains("..."));
+    }
+
+    #[test]
+    fn restore_undoes_changes_since_save() {
+        let mut state = State::new();
+
+        let code = "/x 1 def /a [1 2] def /s (ab) def \
+                    save /x 2 def /y 3 def a 0 9 put s 1 99 put \
+                    1 exch restore x a 0 get s 1 get { y } stopped";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [1, 1, 1, 98] {
+            expected.operand_stack.push(n.into());
+        }
+        expected.operand_stack.push(Item::Bool(true));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn nested_saves_restore_to_the_outer_one() {
+        let mut state = State::new();
+
+        let code = "/x 1 def save /x 2 def save /x 3 def pop restore x";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(1));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn restore_undoes_changes_made_between_and_after_nested_saves() {
+        let mut state = State::new();
+
+        let code = "/a [1] def save a 0 2 put save a 0 3 put restore a 0 get exch \
+                    save a 0 4 put exch restore pop a 0 get \
+                    /b [1] def save save b 0 5 put exch restore pop b 0 get";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [2, 1, 1] {
+            expected.operand_stack.push(Item::Number(n));
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn restore_resets_the_access_rights_of_dictionaries() {
+        let mut state = State::new();
+
+        let code = "/d 1 dict def save d readonly pop d wcheck exch restore d wcheck";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for b in [false, true] {
+            expected.operand_stack.push(b.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn restore_is_invalid_once_restored_or_with_newer_objects_on_the_stack() {
+        let mut state = State::new();
+
+        for code in [
+            "save dup restore restore",
+            "save save exch restore restore",
+            "save [1] exch restore",
+            "save 1 dict begin restore",
+        ] {
+            let err = execute(code, &mut state).unwrap_err();
+            assert!(err.to_string().starts_with("/invalidrestore"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn dictionary_stack_starts_with_the_permanent_dictionaries() {
+        let mut state = State::new();
+
+        let code = "countdictstack currentdict userdict eq \
+                    [0 0 0 0] dictstack dup length exch 0 get systemdict eq \
+                    1 dict begin 2 dict begin countdictstack cleardictstack countdictstack";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            3.into(),
+            true.into(),
+            3.into(),
+            true.into(),
+            5.into(),
+            3.into(),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn dictstack_and_execstack_fill_the_given_array() {
+        let mut state = State::new();
+
+        let code = "/a [0 0 0 0] def a dictstack pop a 0 get systemdict eq a 3 get \
+                    /b [0 0 0] def { b execstack } exec pop b 2 get b 0 get";
+        execute(code, &mut state).unwrap();
+
+        assert!(matches!(state.operand_stack.pop().unwrap(), Item::File(_)));
+        let mut expected = State::new();
+        for item in [true.into(), 0.into(), 0.into()] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        for code in ["[0 0] dictstack", "[] execstack"] {
+            let err = execute(code, &mut State::new()).unwrap_err();
+            assert!(err.to_string().starts_with("/rangecheck"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn end_does_not_pop_permanent_dictionaries() {
+        let mut state = State::new();
+
+        let err = execute("1 dict begin end end", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/dictstackunderflow in end"));
+        assert_eq!(3, state.dict_stack.len());
+    }
+
+    #[test]
+    fn names_are_looked_up_from_the_top_of_the_dictionary_stack() {
+        let mut state = State::new();
+
+        let code = "/add 1 def add 1 dict begin /add 2 def add end add \
+                    userdict begin /add 3 def end add";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [1, 2, 1, 3] {
+            expected.operand_stack.push(n.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn where_finds_the_dictionary_defining_a_name() {
+        let mut state = State::new();
+
+        let code = "/x 1 def 1 dict begin /x where { userdict eq } if /y where \
+                    /add where { systemdict eq } if end";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for b in [true, false, true] {
+            expected.operand_stack.push(b.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn store_replaces_the_visible_definition() {
+        let mut state = State::new();
+
+        let code = "/x 1 def 1 dict begin /x 2 store /y 3 store currentdict /y known end \
+                    x userdict /y known";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [true.into(), 2.into(), false.into()] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn dictionaries_support_get_put_known_and_undef() {
+        let mut state = State::new();
+
+        let code = "/d 5 dict def d /a 1 put d /a get d /a known \
+                    d /a undef d /a known d length d maxlength \
+                    { d /a get } stopped";
+        execute(code, &mut state).unwrap();
+        assert_eq!(Item::Bool(true), state.operand_stack.pop().unwrap());
+        assert_eq!(Item::Key("a".into()), state.operand_stack.pop().unwrap());
+        assert!(matches!(state.operand_stack.pop(), Ok(Item::Dict(_))));
+
+        let mut expected = State::new();
+        for item in [1.into(), true.into(), false.into(), 0.into(), 5.into()] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn strings_that_are_not_utf8_are_distinct_keys() {
+        let mut state = State::new();
+
+        let code = "/d 2 dict def d (\\377) 1 put d (\\376) 2 put d length d (\\377) get";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [2, 1] {
+            expected.operand_stack.push(n.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn maxlength_is_the_size_a_dictionary_was_created_for() {
+        let mut state = State::new();
+
+        let code = "1 dict maxlength 0 dict maxlength 1 dict dup /a 1 put dup /b 2 put maxlength \
+                    << /a 1 >> maxlength";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [1, 0, 2, 1] {
+            expected.operand_stack.push(n.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn forall_pushes_the_entries_of_a_dictionary() {
+        let mut state = State::new();
+
+        let code = "/sum 0 def 3 dict dup /a 1 put dup /b 2 put dup /c 4 put \
+                    { sum add /sum exch def pop } forall sum";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(7));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn any_object_can_be_a_dictionary_key() {
+        let mut state = State::new();
+
+        let code = "/d 10 dict def /a [1] def \
+                    d 1 (one) put d 1.0 get \
+                    d 1.5 (half) put d 1.5 get \
+                    d true 2 put d true get \
+                    d (s) 3 put d /s get \
+                    d a 4 put d a get d [1] known";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            Item::string(b"one".to_vec()),
+            Item::string(b"half".to_vec()),
+            2.into(),
+            3.into(),
+            4.into(),
+            false.into(),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn forall_pushes_keys_as_they_were_stored() {
+        let mut state = State::new();
+
+        let code = "1 dict dup 2.0 (x) put { pop } forall 1 dict dup (k) 0 put { pop } forall";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(2));
+        expected.operand_stack.push(Item::Key("k".into()));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn double_angle_brackets_build_a_dictionary() {
+        let mut state = State::new();
+
+        let code = "<< /a 1 /b [2] >> dup /a get exch /b get 0 get \
+                    /f { << /c 3 >> /c get } def f";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [1, 2, 3] {
+            expected.operand_stack.push(n.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+
+        let err = execute("<< /a 1 /b >>", &mut state).unwrap_err();
+        assert!(err.to_string().starts_with("/rangecheck in >>"));
+    }
+
+    #[test]
+    fn access_rights_can_only_be_reduced() {
+        let mut state = State::new();
+
+        let code = "[1] dup rcheck exch dup wcheck exch \
+                    readonly dup rcheck exch dup wcheck exch \
+                    noaccess rcheck systemdict wcheck";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for b in [true, true, true, false, false, false] {
+            expected.operand_stack.push(b.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn restricting_an_array_or_string_leaves_other_copies_writable() {
+        let mut state = State::new();
+
+        let code = "/a [1 2] def a readonly pop a 0 9 put a 0 get a wcheck \
+                    (abc) dup readonly pop dup 0 65 put 0 get \
+                    /d 1 dict def d readonly pop d wcheck";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [9.into(), true.into(), 65.into(), false.into()] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn invalid_access_is_raised_when_rights_are_violated() {
+        for code in [
+            "[1] readonly 0 2 put",
+            "(a) readonly 0 98 put",
+            "1 dict readonly /a 1 put",
+            "1 dict noaccess /a get",
+            "1 dict noaccess maxlength",
+            "[1] executeonly 0 get",
+            "1 dict readonly begin /a 1 def",
+            "systemdict /add 1 put",
+            "systemdict begin /x 1 def",
+            "/add 1 def /add 2 store userdict /add undef systemdict /add undef",
+            "[1] readonly executeonly readonly",
+        ] {
+            let mut state = State::new();
+            let err = execute(code, &mut state).unwrap_err();
+            assert!(err.to_string().starts_with("/invalidaccess"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn reading_and_executing_require_access() {
+        for code in [
+            "{ 1 } noaccess exec",
+            "(1) cvx noaccess exec",
+            "true { 1 } noaccess if",
+            "2 { 1 } noaccess repeat",
+            "[1 2] noaccess length",
+            "(ab) executeonly length",
+            "{ 1 } executeonly length",
+            "[1 2] executeonly { } forall",
+            "(a) noaccess print",
+            "(1) executeonly token",
+        ] {
+            let mut state = State::new();
+            let err = execute(code, &mut state).unwrap_err();
+            assert!(err.to_string().starts_with("/invalidaccess"), "{}", code);
+        }
+
+        let mut state = State::new();
+        let code = "{ 1 } executeonly exec /p { 2 } executeonly def p (3) cvx executeonly exec";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [1, 2, 3] {
+            expected.operand_stack.push(n.into());
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn integer_operators_divide_and_take_remainders() {
+        let mut state = State::new();
+
+        let code = "7 2 idiv -7 2 idiv 7 -3 mod -7 3 mod -3 abs -3.5 abs 5 2 div";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for item in [
+            3.into(),
+            (-3).into(),
+            1.into(),
+            (-1).into(),
+            3.into(),
+            3.5.into(),
+            2.5.into(),
+        ] {
+            expected.operand_stack.push(item);
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn transcendental_operators_work_in_degrees() {
+        let mut state = State::new();
+
+        let code = "90 sin 180 cos 2 10 exp 100 log 1 ln 4 0.5 exp \
+                    90 cos 180 sin 270 sin -90 cos 450 sin 360 cos";
+        execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        for n in [
+            1.0, -1.0, 1024.0, 2.0, 0.0, 2.0, 0.0, 0.0, -1.0, 0.0, 1.0, 1.0,
+        ] {
+            expected.operand_stack.push(Item::Float(n));
+        }
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn rand_is_reproducible_after_srand() {
+        let mut state = State::new();
+
+        let code = "42 srand rand rand rrand 42 srand rand -5 srand rrand";
+        execute(code, &mut state).unwrap();
+
+        let stack = &state.operand_stack.inner;
+        assert_eq!(705894, stack[0].as_int().unwrap());
+        assert_eq!(stack[1], stack[2]);
+        assert_eq!(stack[0], stack[3]);
+        assert_eq!(Item::Number(6), stack[4]);
+    }
+
+    #[test]
+    fn procedures_must_be_closed_in_the_same_file() {
+        for code in ["({ 1) cvx exec 2 }", "/f { 1 2"] {
+            let mut state = State::new();
+            let err = execute(code, &mut state).unwrap_err();
+            assert!(err.to_string().starts_with("/syntaxerror"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn errors_drop_unfinished_procedures() {
+        let mut state = State::new();
+
+        execute("{ //nope 1 }", &mut state).unwrap_err();
+        execute("3 4", &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::Number(3));
+        expected.operand_stack.push(Item::Number(4));
+        assert_eq!(expected.operand_stack, state.operand_stack);
+    }
+
+    #[test]
+    fn tail_calls_do_not_grow_the_exec_stack() {
+        let mut state = State::new();
+
+        let code = "/f { dup 0 ne { 1 sub f } if } def 1000 f";
+        execute(code, &mut state).unwrap();
+
+        assert_eq!(vec![Item::Number(0)], state.operand_stack.inner);
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// The text of a name, e.g. `add` in `add` or `/add`.
+///
+/// Names are sequences of bytes like strings, so they are kept as scanned and only
+/// decoded when printed. Copies share the text.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Name(Rc<[u8]>);
+
+impl Name {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for Name {
+    fn from(text: &[u8]) -> Self {
+        Name(text.into())
+    }
+}
+
+impl From<Vec<u8>> for Name {
+    fn from(text: Vec<u8>) -> Self {
+        Name(text.into())
+    }
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Name::from(text.as_bytes())
+    }
+}
+
+impl From<String> for Name {
+    fn from(text: String) -> Self {
+        Name::from(text.into_bytes())
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+/// Bytes that aren't UTF-8 are shown as replacement characters.
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
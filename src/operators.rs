@@ -1,33 +1,106 @@
-use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::hash::{BuildHasher, Hasher};
-use std::mem;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
 
-use color_eyre::eyre::{Report, Result};
 use once_cell::sync::OnceCell;
 
-use super::stack::Item;
-use super::State;
+use super::error::{PsError, Result};
+use super::exec::Exec;
+use super::stack::{Access, Array, Block, Composite, DictKey, Dictionary, Item};
+use super::{File, State};
 
 macro_rules! operator {
     ($name:ident, $arity:expr) => {{
         Box::new(move |state: &mut State| {
             #[allow(unused_comparisons)]
             if state.operand_stack.len() < $arity {
-                return Err(Report::msg(format!(
-                    "/stackunderflow in {}",
-                    stringify!($name)
-                )));
+                return Err(PsError::Stackunderflow(String::new()));
             }
 
-            $name(state)
+            call_restoring_operands(state, $arity, $name)
         }) as Box<_>
     }};
 }
 
+/// Call an operator taking `arity` operands, putting them back if it fails.
+///
+/// PostScript leaves the operands of a failing operator on the stack, so error handlers
+/// can inspect them and `stopped` can recover.
+fn call_restoring_operands(
+    state: &mut State,
+    arity: usize,
+    f: fn(&mut State) -> Result<()>,
+) -> Result<()> {
+    let base = state.operand_stack.len() - arity;
+    let operands = state.operand_stack.inner[base..].to_vec();
+    f(state).inspect_err(|_| {
+        let stack = &mut state.operand_stack.inner;
+        stack.truncate(base);
+        stack.extend(operands);
+    })
+}
+
 pub type OperatorFn = dyn Fn(&mut State) -> Result<()> + Send + Sync;
 pub type OperatorMap = HashMap<&'static str, Box<OperatorFn>>;
 
+/// An operator object, as stored in `systemdict`.
+#[derive(Clone, Copy)]
+pub struct Operator {
+    name: &'static str,
+    f: &'static OperatorFn,
+}
+
+impl Operator {
+    pub fn new(name: &'static str, f: &'static OperatorFn) -> Self {
+        Operator { name, f }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn call(&self, state: &mut State) -> Result<()> {
+        (self.f)(state).map_err(|e| e.in_command(self.name))
+    }
+}
+
+impl PartialEq for Operator {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && std::ptr::addr_eq(self.f, other.f)
+    }
+}
+
+impl Eq for Operator {}
+
+impl fmt::Debug for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operator({})", self.name)
+    }
+}
+
+/// Operator objects for all operators in `operators`, keyed by name.
+pub fn operator_dict(operators: &'static OperatorMap) -> HashMap<DictKey, Item> {
+    operators
+        .iter()
+        .map(|(&name, f)| (name.into(), Item::Operator(Operator::new(name, &**f))))
+        .collect()
+}
+
+/// The default `errordict`: a handler for each error recording it in `$error` and
+/// stopping, and `handleerror` to report it.
+pub fn error_dict(operators: &'static OperatorMap) -> HashMap<DictKey, Item> {
+    let operator = |name: &'static str| Item::Operator(Operator::new(name, &*operators[name]));
+    PsError::NAMES
+        .iter()
+        .map(|&name| {
+            let handler = Block::new(vec![Item::Key(name.into()), operator(".error")], Vec::new());
+            (name.into(), Item::Block(handler))
+        })
+        .chain([("handleerror".into(), operator("handleerror"))])
+        .collect()
+}
+
 pub fn operators() -> &'static OperatorMap {
     static OPERATORS: OnceCell<OperatorMap> = OnceCell::new();
     OPERATORS.get_or_init(|| {
@@ -39,8 +112,23 @@ pub fn operators() -> &'static OperatorMap {
         m.insert("mul", operator!(mul, 2));
         m.insert("div", operator!(div, 2));
         m.insert("neg", operator!(neg, 1));
+        m.insert("idiv", operator!(idiv, 2));
+        m.insert("mod", operator!(modulo, 2));
+        m.insert("abs", operator!(abs, 1));
+        m.insert("ceiling", operator!(ceiling, 1));
+        m.insert("floor", operator!(floor, 1));
+        m.insert("round", operator!(round, 1));
+        m.insert("truncate", operator!(truncate, 1));
         m.insert("sqrt", operator!(sqrt, 1));
+        m.insert("sin", operator!(sin, 1));
+        m.insert("cos", operator!(cos, 1));
+        m.insert("atan", operator!(atan, 2));
+        m.insert("exp", operator!(exp, 2));
+        m.insert("ln", operator!(ln, 1));
+        m.insert("log", operator!(log, 1));
         m.insert("rand", operator!(rand, 0));
+        m.insert("srand", operator!(srand, 1));
+        m.insert("rrand", operator!(rrand, 0));
 
         // stack
         m.insert("exch", operator!(exch, 2));
@@ -50,9 +138,21 @@ pub fn operators() -> &'static OperatorMap {
         m.insert("pstack", operator!(pstack, 0));
         m.insert("count", operator!(count, 0));
         m.insert("pdict", operator!(pdict, 0));
+        m.insert("print", operator!(print, 1));
+        m.insert("=", operator!(print_text, 1));
+        m.insert("==", operator!(print_syntax, 1));
 
         // def
         m.insert("def", operator!(def, 2));
+        m.insert("load", operator!(load, 1));
+        m.insert("where", operator!(where_defined, 1));
+        m.insert("store", operator!(store, 2));
+        m.insert("known", operator!(known, 2));
+        m.insert("undef", operator!(undef, 2));
+        m.insert("bind", operator!(bind, 1));
+        m.insert("systemdict", operator!(systemdict, 0));
+        m.insert("globaldict", operator!(globaldict, 0));
+        m.insert("userdict", operator!(userdict, 0));
 
         // control
         m.insert("exec", operator!(exec, 1));
@@ -60,6 +160,16 @@ pub fn operators() -> &'static OperatorMap {
         m.insert("for", operator!(for_loop, 4));
         m.insert("if", operator!(if_cond, 2));
         m.insert("ifelse", operator!(ifelse_cond, 3));
+        m.insert("loop", operator!(loop_forever, 1));
+        m.insert("exit", operator!(exit, 0));
+        m.insert("stop", operator!(stop, 0));
+        m.insert("stopped", operator!(stopped, 1));
+        m.insert("errordict", operator!(errordict, 0));
+        m.insert("$error", operator!(error_info, 0));
+        m.insert("handleerror", operator!(handleerror, 0));
+        m.insert(".error", operator!(default_handler, 2));
+        m.insert("execstack", operator!(execstack, 1));
+        m.insert("countexecstack", operator!(countexecstack, 0));
 
         // relational
         m.insert("true", operator!(bool_true, 0));
@@ -72,86 +182,287 @@ pub fn operators() -> &'static OperatorMap {
         m.insert("]", operator!(array_close, 1));
         m.insert("length", operator!(array_length, 1));
         m.insert("forall", operator!(array_forall, 2));
+        m.insert("get", operator!(get, 2));
+        m.insert("put", operator!(put, 3));
 
         // dict
         m.insert("dict", operator!(dict_new, 1));
+        m.insert("<<", operator!(mark, 0));
+        m.insert(">>", operator!(dict_close, 0));
         m.insert("begin", operator!(dict_begin, 1));
         m.insert("end", operator!(dict_end, 0));
+        m.insert("maxlength", operator!(maxlength, 1));
+        m.insert("currentdict", operator!(currentdict, 0));
+        m.insert("countdictstack", operator!(countdictstack, 0));
+        m.insert("dictstack", operator!(dictstack, 1));
+        m.insert("cleardictstack", operator!(cleardictstack, 0));
+
+        // vm
+        m.insert("save", operator!(save, 0));
+        m.insert("restore", operator!(restore, 1));
+
+        // string
+        m.insert("string", operator!(string_new, 1));
+
+        // file
+        m.insert("currentfile", operator!(currentfile, 0));
+        m.insert("read", operator!(read, 1));
+        m.insert("readline", operator!(readline, 2));
+        m.insert("readstring", operator!(readstring, 2));
+        m.insert("token", operator!(token, 1));
 
         // type
         m.insert("cvi", operator!(cvi, 1));
+        m.insert("cvx", operator!(cvx, 1));
+        m.insert("cvlit", operator!(cvlit, 1));
+        m.insert("xcheck", operator!(xcheck, 1));
+        m.insert("readonly", operator!(readonly, 1));
+        m.insert("executeonly", operator!(executeonly, 1));
+        m.insert("noaccess", operator!(noaccess, 1));
+        m.insert("rcheck", operator!(rcheck, 1));
+        m.insert("wcheck", operator!(wcheck, 1));
 
         m
     })
 }
 
+/// Apply a binary operator to the two numbers on top of the stack.
+///
+/// Integers give an integer result unless `int` overflows, in which case the operands are
+/// promoted to reals like they are when either of them is one. A real result too large
+/// to represent raises `/undefinedresult`.
+fn arithmetic(
+    state: &mut State,
+    name: &str,
+    int: fn(i32, i32) -> Option<i32>,
+    float: fn(f32, f32) -> f32,
+) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let b = stack.pop()?;
+    let a = stack.pop()?;
+
+    if let (Ok(a), Ok(b)) = (a.as_int(), b.as_int()) {
+        if let Some(n) = int(a, b) {
+            stack.push(n.into());
+            return Ok(());
+        }
+    }
+
+    match (a.as_float(), b.as_float()) {
+        (Ok(a), Ok(b)) => {
+            let n = float(a, b);
+            if !n.is_finite() {
+                return Err(PsError::Undefinedresult(name.into()));
+            }
+            stack.push(n.into());
+            Ok(())
+        }
+        _ => Err(PsError::Typecheck(name.into())),
+    }
+}
+
+/// Apply a unary operator to the number on top of the stack, see `arithmetic`.
+fn arithmetic_unary(
+    state: &mut State,
+    name: &str,
+    int: fn(i32) -> Option<i32>,
+    float: fn(f32) -> f32,
+) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let item = stack.pop()?;
+
+    if let Ok(n) = item.as_int() {
+        if let Some(n) = int(n) {
+            stack.push(n.into());
+            return Ok(());
+        }
+    }
+
+    match item.as_float() {
+        Ok(n) => {
+            stack.push(float(n).into());
+            Ok(())
+        }
+        Err(_) => Err(PsError::Typecheck(name.into())),
+    }
+}
+
 fn add(state: &mut State) -> Result<()> {
+    arithmetic(state, "add", i32::checked_add, |a, b| a + b)
+}
+
+fn sub(state: &mut State) -> Result<()> {
+    arithmetic(state, "sub", i32::checked_sub, |a, b| a - b)
+}
+
+fn mul(state: &mut State) -> Result<()> {
+    arithmetic(state, "mul", i32::checked_mul, |a, b| a * b)
+}
+
+fn div(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let res = stack.pop()?.as_int()? + stack.pop()?.as_int()?;
-    stack.push(res.into());
+    let n2 = stack.pop()?.as_float()?;
+    let n1 = stack.pop()?.as_float()?;
+    let n = n1 / n2;
+    if !n.is_finite() {
+        return Err(PsError::Undefinedresult("div".into()));
+    }
+    stack.push(n.into());
+    Ok(())
+}
 
+fn idiv(state: &mut State) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let n2 = stack.pop()?.as_int()?;
+    let n1 = stack.pop()?.as_int()?;
+    match n1.checked_div(n2) {
+        Some(n) => stack.push(n.into()),
+        None => return Err(PsError::Undefinedresult("idiv".into())),
+    }
     Ok(())
 }
 
-fn sub(state: &mut State) -> Result<()> {
+fn modulo(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
     let n2 = stack.pop()?.as_int()?;
     let n1 = stack.pop()?.as_int()?;
-    stack.push((n1 - n2).into());
+    match n2 {
+        0 => return Err(PsError::Undefinedresult("mod".into())),
+        // The sign of the result follows the dividend, like `%` does.
+        n2 => stack.push(n1.wrapping_rem(n2).into()),
+    }
     Ok(())
 }
 
-fn mul(state: &mut State) -> Result<()> {
+fn neg(state: &mut State) -> Result<()> {
+    arithmetic_unary(state, "neg", i32::checked_neg, |n| -n)
+}
+
+fn abs(state: &mut State) -> Result<()> {
+    arithmetic_unary(state, "abs", i32::checked_abs, f32::abs)
+}
+
+fn ceiling(state: &mut State) -> Result<()> {
+    arithmetic_unary(state, "ceiling", Some, f32::ceil)
+}
+
+fn floor(state: &mut State) -> Result<()> {
+    arithmetic_unary(state, "floor", Some, f32::floor)
+}
+
+fn round(state: &mut State) -> Result<()> {
+    // Halfway cases round up, so -2.5 becomes -2.0.
+    arithmetic_unary(state, "round", Some, |n| {
+        if n.fract() == -0.5 {
+            n.ceil()
+        } else {
+            n.round()
+        }
+    })
+}
+
+fn truncate(state: &mut State) -> Result<()> {
+    arithmetic_unary(state, "truncate", Some, f32::trunc)
+}
+
+fn sqrt(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let a = stack.pop()?;
-    let b = stack.pop()?;
+    let n = stack.pop()?.as_float()?;
+    stack.push(n.sqrt().into());
+    Ok(())
+}
 
-    if let (Ok(a), Ok(b)) = (a.as_int(), b.as_int()) {
-        stack.push((a * b).into());
-        return Ok(());
+/// The sine of an angle in degrees, exact at multiples of 90.
+fn sin_degrees(angle: f64) -> f32 {
+    match angle.rem_euclid(360.0) {
+        0.0 | 180.0 => 0.0,
+        90.0 => 1.0,
+        270.0 => -1.0,
+        angle => angle.to_radians().sin() as f32,
     }
+}
 
-    if let (Ok(a), Ok(b)) = (a.as_float(), b.as_float()) {
-        stack.push((a * b).into());
-        return Ok(());
-    }
+fn sin(state: &mut State) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let angle = stack.pop()?.as_float()?;
+    stack.push(sin_degrees(angle.into()).into());
+    Ok(())
+}
 
-    Err(Report::msg("/typecheck in --mul--"))
+fn cos(state: &mut State) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let angle = stack.pop()?.as_float()?;
+    stack.push(sin_degrees(f64::from(angle) + 90.0).into());
+    Ok(())
 }
 
-fn div(state: &mut State) -> Result<()> {
+/// The angle in degrees, between 0 and 360, whose tangent is `num`/`den`.
+fn atan(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let n2 = stack.pop()?.as_float()?;
-    let n1 = stack.pop()?.as_float()?;
-    stack.push((n1 / n2).into());
+    let den = stack.pop()?.as_float()?;
+    let num = stack.pop()?.as_float()?;
+    if num == 0.0 && den == 0.0 {
+        return Err(PsError::Undefinedresult("atan".into()));
+    }
+    let angle = num.atan2(den).to_degrees();
+    stack.push(if angle < 0.0 { angle + 360.0 } else { angle }.into());
     Ok(())
 }
 
-fn neg(state: &mut State) -> Result<()> {
+fn exp(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let n = stack.pop()?.as_int()?;
-    stack.push((-n).into());
+    let exponent = stack.pop()?.as_float()?;
+    let base = stack.pop()?.as_float()?;
+    let n = base.powf(exponent);
+    if !n.is_finite() {
+        return Err(PsError::Undefinedresult("exp".into()));
+    }
+    stack.push(n.into());
     Ok(())
 }
 
-fn sqrt(state: &mut State) -> Result<()> {
+fn ln(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
     let n = stack.pop()?.as_float()?;
-    stack.push(n.sqrt().into());
+    if n <= 0.0 {
+        return Err(PsError::Rangecheck("ln".into()));
+    }
+    stack.push(n.ln().into());
     Ok(())
 }
 
-fn rand(state: &mut State) -> Result<()> {
+fn log(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let random_value = RandomState::new().build_hasher().finish() as i32;
-    stack.push(random_value.into());
+    let n = stack.pop()?.as_float()?;
+    if n <= 0.0 {
+        return Err(PsError::Rangecheck("log".into()));
+    }
+    stack.push(n.log10().into());
+    Ok(())
+}
+
+fn rand(state: &mut State) -> Result<()> {
+    let n = state.next_rand();
+    state.operand_stack.push(n.into());
+    Ok(())
+}
+
+fn srand(state: &mut State) -> Result<()> {
+    let seed = state.operand_stack.pop()?.as_int()?;
+    state.set_rand_seed(seed);
+    Ok(())
+}
+
+fn rrand(state: &mut State) -> Result<()> {
+    let seed = state.rand_state;
+    state.operand_stack.push(seed.into());
     Ok(())
 }
 
 fn exch(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let a = stack.pop().unwrap();
-    let b = stack.pop().unwrap();
+    let a = stack.pop()?;
+    let b = stack.pop()?;
     stack.push(a);
     stack.push(b);
     Ok(())
@@ -167,7 +478,7 @@ fn dup(state: &mut State) -> Result<()> {
 
 fn pop(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let _a = stack.pop();
+    stack.pop()?;
     Ok(())
 }
 
@@ -180,7 +491,7 @@ fn clear(state: &mut State) -> Result<()> {
 fn pstack(state: &mut State) -> Result<()> {
     let stack = &state.operand_stack.inner;
     for x in stack.iter().rev() {
-        println!("{:?}", x);
+        println!("{}", x);
     }
     Ok(())
 }
@@ -193,75 +504,379 @@ fn count(state: &mut State) -> Result<()> {
 }
 
 fn pdict(state: &mut State) -> Result<()> {
-    let dict = &state.dictionary;
-    for (k, v) in dict {
+    let dict = state.current_dict().borrow();
+    for (k, v) in dict.iter() {
         println!("{}: {:?}", k, v);
     }
     Ok(())
 }
 
+fn print(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    let s = item.as_string()?;
+    s.check_read()?;
+    let mut stdout = io::stdout();
+    stdout.write_all(&s.borrow())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn print_text(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    let mut stdout = io::stdout();
+    write_text(&mut stdout, &item)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Writes the text of an object as `=` prints it, regardless of its executable attribute.
+fn write_text(out: &mut impl Write, item: &Item) -> io::Result<()> {
+    match item.literal() {
+        Item::String(s) => out.write_all(&s.borrow()),
+        Item::Key(k) | Item::Name(k) => out.write_all(k.as_bytes()),
+        item @ (Item::Number(_) | Item::Float(_) | Item::Bool(_) | Item::Operator(_)) => {
+            write!(out, "{}", item)
+        }
+        _ => write!(out, "--nostringval--"),
+    }
+}
+
+fn print_syntax(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    println!("{}", item);
+    Ok(())
+}
+
 fn def(state: &mut State) -> Result<()> {
     let item = state.operand_stack.pop()?;
     let name = state.operand_stack.pop()?;
 
-    state.dictionary.insert(name.as_key()?.to_string(), item);
+    let dict = state.current_dict().clone();
+    dict.check_write()?;
+    state.journal(&Item::Dict(dict.clone()));
+    dict.borrow_mut().insert(name.into(), item);
     Ok(())
 }
 
-fn exec(state: &mut State) -> Result<()> {
-    let code = state.operand_stack.pop()?.as_block()?.to_string();
+fn where_defined(state: &mut State) -> Result<()> {
+    let key = state.operand_stack.pop()?.into();
+    match state.find_dict(&key).cloned() {
+        Some(dict) => {
+            state.operand_stack.push(Item::Dict(dict));
+            state.operand_stack.push(Item::Bool(true));
+        }
+        None => state.operand_stack.push(Item::Bool(false)),
+    }
+    Ok(())
+}
+
+fn store(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    let name = state.operand_stack.pop()?;
 
-    super::execute(&code, state, operators())?;
+    let name = name.into();
+    let dict = state
+        .find_dict(&name)
+        .unwrap_or(state.current_dict())
+        .clone();
+    dict.check_write()?;
+    state.journal(&Item::Dict(dict.clone()));
+    dict.borrow_mut().insert(name, item);
     Ok(())
 }
 
-fn repeat(state: &mut State) -> Result<()> {
-    let proc = state.operand_stack.pop()?.as_block()?.to_string();
-    let n = state.operand_stack.pop()?.as_int()?;
+fn known(state: &mut State) -> Result<()> {
+    let key = state.operand_stack.pop()?;
+    let dict = state.operand_stack.pop()?.into_dict()?;
+    dict.check_read()?;
+    let known = dict.borrow().contains_key(&key.into());
+    state.operand_stack.push(Item::Bool(known));
+    Ok(())
+}
 
-    for i in 0..n {
-        state.operand_stack.push(i.into());
-        super::execute(&proc, state, operators())?;
+fn undef(state: &mut State) -> Result<()> {
+    let key = state.operand_stack.pop()?;
+    let dict = state.operand_stack.pop()?.into_dict()?;
+    dict.check_write()?;
+    state.journal(&Item::Dict(dict.clone()));
+    dict.borrow_mut().remove(&key.into());
+    Ok(())
+}
+
+fn load(state: &mut State) -> Result<()> {
+    let key = DictKey::from(state.operand_stack.pop()?);
+    match state.get(key.clone()) {
+        Some(item) => {
+            state.operand_stack.push(item);
+            Ok(())
+        }
+        None => Err(PsError::Undefined(key.to_string())),
     }
+}
+
+fn bind(state: &mut State) -> Result<()> {
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    bind_block(&proc, state, &mut vec![]);
+    state.operand_stack.push(Item::Block(proc));
     Ok(())
 }
 
-fn for_loop(state: &mut State) -> Result<()> {
-    let proc = state.operand_stack.pop()?.as_block()?.to_string();
-    let limit = state.operand_stack.pop()?.as_int()?;
-    let inc = state.operand_stack.pop()?.as_int()?;
-    let init = state.operand_stack.pop()?.as_int()?;
+/// Replace names referring to operators with the operators in place, including in nested
+/// procedures. Procedures in `seen` were bound already, read-only ones are left alone.
+fn bind_block(block: &Block, state: &mut State, seen: &mut Vec<Array>) {
+    if seen.iter().any(|items| Array::ptr_eq(items, &block.items)) {
+        return;
+    }
+    if block.items.check_write().is_err() {
+        return;
+    }
+    seen.push(block.items.clone());
+    state.journal(&Item::Block(block.clone()));
+
+    let mut nested = vec![];
+    for item in block.items.borrow_mut().iter_mut() {
+        match item {
+            Item::Name(name) => {
+                if let Some(op @ Item::Operator(_)) = state.get(name.clone()) {
+                    *item = op;
+                }
+            }
+            Item::Block(block) => nested.push(block.clone()),
+            _ => (),
+        }
+    }
 
-    for i in (init..=limit).step_by(inc as usize) {
-        state.operand_stack.push(i.into());
-        super::execute(&proc, state, operators())?;
+    for block in nested {
+        bind_block(&block, state, seen);
     }
+}
+
+fn systemdict(state: &mut State) -> Result<()> {
+    state
+        .operand_stack
+        .push(Item::Dict(state.systemdict().clone()));
+    Ok(())
+}
+
+fn globaldict(state: &mut State) -> Result<()> {
+    let dict = state.dict_stack.inner[1].clone();
+    state.operand_stack.push(Item::Dict(dict));
+    Ok(())
+}
+
+fn userdict(state: &mut State) -> Result<()> {
+    let dict = state.dict_stack.inner[2].clone();
+    state.operand_stack.push(Item::Dict(dict));
     Ok(())
 }
 
+fn exec(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    super::execute_object(item, state)
+}
+
+fn repeat(state: &mut State) -> Result<()> {
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    let count = state.operand_stack.pop()?.as_int()?;
+    if count < 0 {
+        return Err(PsError::Rangecheck("repeat".into()));
+    }
+
+    state.push_exec(Exec::Repeat { count, proc })
+}
+
+fn for_loop(state: &mut State) -> Result<()> {
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    let limit = state.operand_stack.pop()?;
+    limit.as_float()?;
+    let increment = state.operand_stack.pop()?;
+    let init = state.operand_stack.pop()?;
+
+    // The control variable is an integer only if both the initial value and increment are.
+    let (control, increment) = match (init, increment) {
+        (init @ Item::Number(_), increment @ Item::Number(_)) => (init, increment),
+        (init, increment) => (
+            Item::Float(init.as_float()?),
+            Item::Float(increment.as_float()?),
+        ),
+    };
+
+    state.push_exec(Exec::For {
+        control,
+        increment,
+        limit,
+        proc,
+    })
+}
+
 fn if_cond(state: &mut State) -> Result<()> {
-    let proc = state.operand_stack.pop()?.as_block()?.to_string();
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
     let cond = state.operand_stack.pop()?.as_bool()?;
 
     if cond {
-        super::execute(&proc, state, operators())?;
+        state.push_exec(Exec::block(proc))?;
     }
     Ok(())
 }
 
 fn ifelse_cond(state: &mut State) -> Result<()> {
-    let proc2 = state.operand_stack.pop()?.as_block()?.to_string();
-    let proc1 = state.operand_stack.pop()?.as_block()?.to_string();
+    let proc2 = state.operand_stack.pop()?.as_block()?.clone();
+    let proc1 = state.operand_stack.pop()?.as_block()?.clone();
     let cond = state.operand_stack.pop()?.as_bool()?;
 
     if cond {
-        super::execute(&proc1, state, operators())?;
+        state.push_exec(Exec::block(proc1))
     } else {
-        super::execute(&proc2, state, operators())?;
+        state.push_exec(Exec::block(proc2))
+    }
+}
+
+fn loop_forever(state: &mut State) -> Result<()> {
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    state.push_exec(Exec::Loop { proc })
+}
+
+fn exit(state: &mut State) -> Result<()> {
+    // `exit` must not leave a `stopped` context or a file on its way to the innermost loop.
+    let index = state.exec_stack.iter().rposition(|exec| {
+        exec.is_loop() || matches!(exec, Exec::Stopped { .. } | Exec::File { .. })
+    });
+    match index {
+        Some(index) if state.exec_stack[index].is_loop() => {
+            state.exec_stack.truncate(index);
+            Ok(())
+        }
+        _ => Err(PsError::Invalidexit("exit".into())),
+    }
+}
+
+fn stop(state: &mut State) -> Result<()> {
+    if !state.stop(0) {
+        return Err(PsError::Invalidstop("stop".into()));
     }
     Ok(())
 }
 
+fn stopped(state: &mut State) -> Result<()> {
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    state.push_exec(Exec::Stopped { proc: proc.clone() })?;
+    state.push_exec(Exec::block(proc))
+}
+
+fn errordict(state: &mut State) -> Result<()> {
+    state
+        .operand_stack
+        .push(Item::Dict(state.errordict.clone()));
+    Ok(())
+}
+
+fn error_info(state: &mut State) -> Result<()> {
+    state
+        .operand_stack
+        .push(Item::Dict(state.error_info.clone()));
+    Ok(())
+}
+
+/// `command /name .error`: what the default `errordict` handlers do.
+///
+/// Records the error in `$error` with the stacks as they were when it was raised, then
+/// stops. Without a `stopped` to catch it, the error is returned from `execute`.
+fn default_handler(state: &mut State) -> Result<()> {
+    let name = state.operand_stack.pop()?.as_key()?.to_string();
+    let command = state.operand_stack.pop()?;
+    let handler = state
+        .exec_stack
+        .iter()
+        .rposition(|exec| matches!(exec, Exec::Handler { .. }));
+    let depth = handler.unwrap_or(state.exec_stack.len());
+    state.record_error(name, command, depth);
+    if state.stop(0) {
+        return Ok(());
+    }
+
+    let Some(index) = handler else {
+        return Err(PsError::Invalidstop(".error".into()));
+    };
+    state.exec_stack.truncate(index + 1);
+    if let Some(Exec::Handler { error, .. }) = state.exec_stack.pop() {
+        state.uncaught = Some(error);
+    }
+    Ok(())
+}
+
+fn handleerror(state: &mut State) -> Result<()> {
+    state.journal(&Item::Dict(state.error_info.clone()));
+    let mut info = state.error_info.borrow_mut();
+    if info.get(&"newerror".into()) == Some(&Item::Bool(true)) {
+        print!("{}", error_report(&info));
+        io::stdout().flush()?;
+        info.insert("newerror".into(), Item::Bool(false));
+    }
+    Ok(())
+}
+
+/// Describe the error recorded in `$error` the way Ghostscript does.
+fn error_report(info: &HashMap<DictKey, Item>) -> String {
+    let mut report = String::new();
+    let name = info
+        .get(&"errorname".into())
+        .and_then(|item| item.as_key().ok())
+        .map(ToString::to_string);
+    let command = info.get(&"command".into()).map(Item::to_string);
+    report.push_str(&format!(
+        "Error: /{} in {}\n",
+        name.unwrap_or_default(),
+        command.unwrap_or_default()
+    ));
+
+    for (title, key) in [
+        ("Operand stack", "ostack"),
+        ("Execution stack", "estack"),
+        ("Dictionary stack", "dstack"),
+    ] {
+        report.push_str(title);
+        report.push_str(":\n");
+        if let Some(Item::Array(items)) = info.get(&key.into()) {
+            for item in items.borrow().iter() {
+                report.push_str(&format!("   {}", item));
+            }
+            report.push('\n');
+        }
+    }
+
+    report
+}
+
+/// Store `items` at the start of the array on top of the stack and push them as an array,
+/// like `readline` does with its string. Raises `/rangecheck` if the array is too short.
+fn store_stack(state: &mut State, name: &str, items: Vec<Item>) -> Result<()> {
+    let array = state.operand_stack.pop()?;
+    let target = array.as_array()?;
+    target.check_write()?;
+    if target.borrow().len() < items.len() {
+        return Err(PsError::Rangecheck(name.into()));
+    }
+    state.journal(&array);
+    target.borrow_mut()[..items.len()].clone_from_slice(&items);
+    state.operand_stack.push(items.into());
+    Ok(())
+}
+
+fn execstack(state: &mut State) -> Result<()> {
+    let items = state
+        .exec_stack
+        .iter()
+        .map(Exec::to_item)
+        .collect::<Vec<_>>();
+    store_stack(state, "execstack", items)
+}
+
+fn countexecstack(state: &mut State) -> Result<()> {
+    let len = state.exec_stack.len() as i32;
+    state.operand_stack.push(len.into());
+    Ok(())
+}
+
 fn mark(state: &mut State) -> Result<()> {
     state.operand_stack.push(Item::Mark);
     Ok(())
@@ -269,37 +884,144 @@ fn mark(state: &mut State) -> Result<()> {
 
 fn array_close(state: &mut State) -> Result<()> {
     let stack = &mut state.operand_stack;
-    let found = (&stack.inner)
+    let found = stack
+        .inner
         .iter()
         .rposition(|item| matches!(item, Item::Mark));
 
     if found.is_none() {
-        return Err(Report::msg("/unmatchedmark in --]--"));
+        return Err(PsError::Unmatchedmark("]".into()));
     }
 
     let pos = found.unwrap();
     let mut items: Vec<_> = stack.inner.drain(pos..).collect();
     items.remove(0); // Mark
-    stack.push(Item::Array(items));
+    stack.push(items.into());
     Ok(())
 }
 
 fn array_length(state: &mut State) -> Result<()> {
     let item = state.operand_stack.pop()?;
-    let array = item.as_array()?;
-    let len = array.len() as i32;
+    let len = match item {
+        Item::Block(block) => {
+            block.items.check_read()?;
+            block.len() as i32
+        }
+        Item::Dict(dict) => {
+            dict.check_read()?;
+            dict.borrow().len() as i32
+        }
+        item => match item.as_string() {
+            Ok(s) => {
+                s.check_read()?;
+                s.borrow().len() as i32
+            }
+            Err(_) => {
+                let items = item.as_array()?;
+                items.check_read()?;
+                items.borrow().len() as i32
+            }
+        },
+    };
     let stack = &mut state.operand_stack;
     stack.push(len.into());
     Ok(())
 }
 
 fn array_forall(state: &mut State) -> Result<()> {
-    let proc = state.operand_stack.pop()?.as_block()?.to_string();
-    let array = state.operand_stack.pop()?.as_array()?.to_vec();
+    let proc = state.operand_stack.pop()?.as_block()?.clone();
+    let container = state.operand_stack.pop()?;
+    if let Item::Dict(dict) = container {
+        dict.check_read()?;
+        let entries = dict.borrow().clone().into_iter().collect();
+        return state.push_exec(Exec::ForallDict {
+            entries,
+            pos: 0,
+            proc,
+        });
+    }
+    let items = container.as_array()?.clone();
+    items.check_read()?;
+
+    state.push_exec(Exec::Forall {
+        items,
+        pos: 0,
+        proc,
+    })
+}
 
-    for elem in array.into_iter() {
-        state.operand_stack.push(elem);
-        super::execute(&proc, state, operators()).expect("can't run block");
+fn get(state: &mut State) -> Result<()> {
+    let index = state.operand_stack.pop()?;
+    let container = state.operand_stack.pop()?;
+    if let Item::Dict(dict) = container {
+        dict.check_read()?;
+        let key = DictKey::from(index);
+        let item = dict.borrow().get(&key).cloned();
+        return match item {
+            Some(item) => {
+                state.operand_stack.push(item);
+                Ok(())
+            }
+            None => Err(PsError::Undefined(key.to_string())),
+        };
+    }
+    let index = usize::try_from(index.as_int()?).ok();
+
+    let item = match &container {
+        Item::Array(items) | Item::Block(Block { items, .. }) => {
+            items.check_read()?;
+            index.and_then(|index| items.borrow().get(index).cloned())
+        }
+        item => {
+            let s = item.as_string()?;
+            s.check_read()?;
+            let s = s.borrow();
+            index.and_then(|index| s.get(index).map(|&c| Item::Number(c as i32)))
+        }
+    };
+
+    match item {
+        Some(item) => {
+            state.operand_stack.push(item);
+            Ok(())
+        }
+        None => Err(PsError::Rangecheck("get".into())),
+    }
+}
+
+fn put(state: &mut State) -> Result<()> {
+    let value = state.operand_stack.pop()?;
+    let index = state.operand_stack.pop()?;
+    let container = state.operand_stack.pop()?;
+    if let Item::Dict(dict) = &container {
+        dict.check_write()?;
+        state.journal(&container);
+        dict.borrow_mut().insert(index.into(), value);
+        return Ok(());
+    }
+    let index = usize::try_from(index.as_int()?).ok();
+
+    match &container {
+        Item::Array(items) | Item::Block(Block { items, .. }) => {
+            items.check_write()?;
+            state.journal(&container);
+            let mut items = items.borrow_mut();
+            match index.and_then(|index| items.get_mut(index)) {
+                Some(slot) => *slot = value,
+                None => return Err(PsError::Rangecheck("put".into())),
+            }
+        }
+        item => {
+            let s = item.as_string()?;
+            s.check_write()?;
+            state.journal(&container);
+            let mut s = s.borrow_mut();
+            let byte = u8::try_from(value.as_int()?);
+            match (index.and_then(|index| s.get_mut(index)), byte) {
+                (Some(slot), Ok(byte)) => *slot = byte,
+                _ => return Err(PsError::Rangecheck("put".into())),
+            }
+        }
     }
     Ok(())
 }
@@ -318,7 +1040,7 @@ fn eq(state: &mut State) -> Result<()> {
     let a = state.operand_stack.pop()?;
     let b = state.operand_stack.pop()?;
 
-    state.operand_stack.push((a == b).into());
+    state.operand_stack.push(a.equals(&b).into());
     Ok(())
 }
 
@@ -326,27 +1048,207 @@ fn ne(state: &mut State) -> Result<()> {
     let a = state.operand_stack.pop()?;
     let b = state.operand_stack.pop()?;
 
-    state.operand_stack.push((a != b).into());
+    state.operand_stack.push((!a.equals(&b)).into());
     Ok(())
 }
 
+/// The most entries `dict` makes room for up front, larger dictionaries grow as needed.
+const MAX_DICT_PREALLOCATION: usize = 1024;
+
+/// The longest string `string` creates, which is the limit PostScript documents.
+const MAX_STRING_LENGTH: i32 = 65535;
+
 fn dict_new(state: &mut State) -> Result<()> {
     let n = state.operand_stack.pop()?.as_int()?;
-    let dict = HashMap::with_capacity(n as usize);
-    state.operand_stack.push(dict.into());
+    if n < 0 {
+        return Err(PsError::Rangecheck("dict".into()));
+    }
+    let dict = HashMap::with_capacity((n as usize).min(MAX_DICT_PREALLOCATION));
+    let dict = Dictionary::with_max_length(dict, n as usize);
+    state.operand_stack.push(Item::Dict(Rc::new(dict)));
+    Ok(())
+}
+
+fn maxlength(state: &mut State) -> Result<()> {
+    let dict = state.operand_stack.pop()?.into_dict()?;
+    dict.check_read()?;
+    let max_length = dict.max_length() as i32;
+    state.operand_stack.push(max_length.into());
+    Ok(())
+}
+
+fn dict_close(state: &mut State) -> Result<()> {
+    let stack = &mut state.operand_stack;
+    let Some(pos) = stack
+        .inner
+        .iter()
+        .rposition(|item| matches!(item, Item::Mark))
+    else {
+        return Err(PsError::Unmatchedmark(">>".into()));
+    };
+
+    let items: Vec<_> = stack.inner.drain(pos + 1..).collect();
+    if !items.len().is_multiple_of(2) {
+        stack.inner.extend(items);
+        return Err(PsError::Rangecheck(">>".into()));
+    }
+
+    stack.pop()?; // Mark
+    let dict: HashMap<_, _> = items
+        .chunks(2)
+        .map(|pair| (pair[0].clone().into(), pair[1].clone()))
+        .collect();
+    stack.push(dict.into());
     Ok(())
 }
 
 fn dict_begin(state: &mut State) -> Result<()> {
     let dict = state.operand_stack.pop()?.into_dict()?;
-    let olddict = mem::replace(&mut state.dictionary, dict);
-    state.dict_stack.push(olddict);
+    state.dict_stack.push(dict);
     Ok(())
 }
 
 fn dict_end(state: &mut State) -> Result<()> {
-    let newdict = state.dict_stack.pop()?;
-    let _olddict = mem::replace(&mut state.dictionary, newdict);
+    state.end()
+}
+
+fn currentdict(state: &mut State) -> Result<()> {
+    let dict = state.current_dict().clone();
+    state.operand_stack.push(Item::Dict(dict));
+    Ok(())
+}
+
+fn countdictstack(state: &mut State) -> Result<()> {
+    let len = state.dict_stack.len() as i32;
+    state.operand_stack.push(len.into());
+    Ok(())
+}
+
+fn dictstack(state: &mut State) -> Result<()> {
+    let items = state
+        .dicts()
+        .map(|dict| Item::Dict(dict.clone()))
+        .collect::<Vec<_>>();
+    store_stack(state, "dictstack", items)
+}
+
+fn cleardictstack(state: &mut State) -> Result<()> {
+    state.clear_dicts();
+    Ok(())
+}
+
+fn save(state: &mut State) -> Result<()> {
+    let id = state.save();
+    state.operand_stack.push(Item::Save(id));
+    Ok(())
+}
+
+fn restore(state: &mut State) -> Result<()> {
+    let Item::Save(id) = state.operand_stack.pop()? else {
+        return Err(PsError::Typecheck(String::new()));
+    };
+    state.restore(id)
+}
+
+fn string_new(state: &mut State) -> Result<()> {
+    let n = state.operand_stack.pop()?.as_int()?;
+    if n < 0 {
+        return Err(PsError::Rangecheck("string".into()));
+    }
+    if n > MAX_STRING_LENGTH {
+        return Err(PsError::Limitcheck("string".into()));
+    }
+    state.operand_stack.push(Item::string(vec![0; n as usize]));
+    Ok(())
+}
+
+fn currentfile(state: &mut State) -> Result<()> {
+    let file = match state.current_file() {
+        Some(file) => file.clone(),
+        None => File::from_bytes(vec![]),
+    };
+    state.operand_stack.push(Item::File(file));
+    Ok(())
+}
+
+fn read(state: &mut State) -> Result<()> {
+    let file = state.operand_stack.pop()?.as_file()?.clone();
+    let byte = file.scanner().read_byte()?;
+    match byte {
+        Some(c) => {
+            state.operand_stack.push((c as i32).into());
+            state.operand_stack.push(true.into());
+        }
+        None => state.operand_stack.push(false.into()),
+    }
+    Ok(())
+}
+
+fn readline(state: &mut State) -> Result<()> {
+    let buffer = state.operand_stack.pop()?.as_string()?.clone();
+    buffer.check_write()?;
+    let file = state.operand_stack.pop()?.as_file()?.clone();
+    let (line, found_eol) = file.scanner().read_line()?;
+    if line.len() > buffer.borrow().len() {
+        return Err(PsError::Rangecheck("readline".into()));
+    }
+    state.journal(&Item::String(buffer.clone()));
+    buffer.borrow_mut()[..line.len()].copy_from_slice(&line);
+    state.operand_stack.push(Item::string(line));
+    state.operand_stack.push(found_eol.into());
+    Ok(())
+}
+
+fn readstring(state: &mut State) -> Result<()> {
+    let buffer = state.operand_stack.pop()?.as_string()?.clone();
+    buffer.check_write()?;
+    let capacity = buffer.borrow().len();
+    let file = state.operand_stack.pop()?.as_file()?.clone();
+    let mut s = Vec::with_capacity(capacity);
+    {
+        let mut scanner = file.scanner();
+        while s.len() < capacity {
+            match scanner.read_byte()? {
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+    }
+    let filled = s.len() == capacity;
+    state.journal(&Item::String(buffer.clone()));
+    buffer.borrow_mut()[..s.len()].copy_from_slice(&s);
+    state.operand_stack.push(Item::string(s));
+    state.operand_stack.push(filled.into());
+    Ok(())
+}
+
+fn token(state: &mut State) -> Result<()> {
+    match state.operand_stack.pop()? {
+        Item::File(file) => match super::scan_object(&file, state)? {
+            Some(item) => {
+                state.operand_stack.push(item);
+                state.operand_stack.push(true.into());
+            }
+            None => state.operand_stack.push(false.into()),
+        },
+        Item::String(s) => {
+            s.check_read()?;
+            let s = s.borrow().clone();
+            let file = File::from_bytes(s.clone());
+            match super::scan_object(&file, state)? {
+                Some(item) => {
+                    let offset = file.scanner().offset();
+                    state.operand_stack.push(Item::string(s[offset..].to_vec()));
+                    state.operand_stack.push(item);
+                    state.operand_stack.push(true.into());
+                }
+                None => state.operand_stack.push(false.into()),
+            }
+        }
+        _ => {
+            return Err(PsError::Typecheck("token".into()));
+        }
+    }
     Ok(())
 }
 
@@ -354,9 +1256,75 @@ fn cvi(state: &mut State) -> Result<()> {
     let elem = state.operand_stack.pop()?;
     if let Ok(i) = elem.as_int() {
         state.operand_stack.push(i.into());
-    } else if let Ok(i) = elem.as_float() {
-        state.operand_stack.push((i as i32).into());
+        return Ok(());
     }
+
+    let f = elem.as_float()?.trunc();
+    if !(i32::MIN as f32..=i32::MAX as f32).contains(&f) {
+        return Err(PsError::Rangecheck("cvi".into()));
+    }
+    state.operand_stack.push((f as i32).into());
+    Ok(())
+}
+
+fn cvx(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    state.operand_stack.push(item.into_executable());
+    Ok(())
+}
+
+fn cvlit(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    state.operand_stack.push(item.into_literal());
+    Ok(())
+}
+
+fn xcheck(state: &mut State) -> Result<()> {
+    let item = state.operand_stack.pop()?;
+    state.operand_stack.push(item.is_executable().into());
+    Ok(())
+}
+
+/// Reduce the access rights of the object on top of the stack, leaving it there.
+fn restrict(state: &mut State, access: Access) -> Result<()> {
+    let mut item = state.operand_stack.pop()?;
+    // Only dictionaries keep their rights with the contents, where `restore` resets them.
+    if let Ok(dict) = item.clone().into_dict() {
+        state.journal(&Item::Dict(dict));
+    }
+    item.restrict(access)?;
+    state.operand_stack.push(item);
+    Ok(())
+}
+
+fn readonly(state: &mut State) -> Result<()> {
+    restrict(state, Access::ReadOnly)
+}
+
+fn executeonly(state: &mut State) -> Result<()> {
+    if let Some(Item::Dict(_)) = state.operand_stack.inner.last() {
+        return Err(PsError::Typecheck(String::new()));
+    }
+    restrict(state, Access::ExecuteOnly)
+}
+
+fn noaccess(state: &mut State) -> Result<()> {
+    restrict(state, Access::None)
+}
+
+fn rcheck(state: &mut State) -> Result<()> {
+    let access = state.operand_stack.pop()?.access()?;
+    state
+        .operand_stack
+        .push((access >= Access::ReadOnly).into());
+    Ok(())
+}
+
+fn wcheck(state: &mut State) -> Result<()> {
+    let access = state.operand_stack.pop()?.access()?;
+    state
+        .operand_stack
+        .push((access == Access::Unlimited).into());
     Ok(())
 }
 
@@ -364,6 +1332,19 @@ fn cvi(state: &mut State) -> Result<()> {
 mod test {
     use super::*;
 
+    #[test]
+    fn write_text_prints_the_text_of_executable_names_and_strings() {
+        let mut state = State::new();
+        crate::execute("/a cvx (b) cvx /c 1 [2]", &mut state).unwrap();
+
+        let mut text = Vec::new();
+        for item in state.operand_stack.inner.iter() {
+            write_text(&mut text, item).unwrap();
+            text.push(b' ');
+        }
+        assert_eq!(&b"a b c 1 --nostringval-- "[..], &text[..]);
+    }
+
     #[test]
     fn add_adds_the_two_top_most_elements() {
         let mut state = State::new();
@@ -435,13 +1416,12 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "typecheck")]
     fn mul_fails_typecheck() {
         let mut state = State::new();
         state.operand_stack.push("a".to_string().into());
         state.operand_stack.push("b".to_string().into());
 
-        mul(&mut state).unwrap();
+        assert_eq!(Err(PsError::Typecheck("mul".into())), mul(&mut state));
     }
 
     #[test]
@@ -471,6 +1451,133 @@ mod test {
         assert_eq!(state, expected);
     }
 
+    #[test]
+    fn arithmetic_promotes_to_real() {
+        let mut state = State::new();
+        state.operand_stack.push(1.into());
+        state.operand_stack.push(0.5.into());
+
+        add(&mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(1.5.into());
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn arithmetic_overflows_to_real() {
+        let mut state = State::new();
+        state.operand_stack.push(i32::MIN.into());
+        state.operand_stack.push(1.into());
+
+        sub(&mut state).unwrap();
+        neg(&mut state).unwrap();
+
+        let mut expected = State::new();
+        expected
+            .operand_stack
+            .push((-(i32::MIN as f32 - 1.0)).into());
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn real_overflow_is_an_undefined_result() {
+        for (f, name, a, b) in [
+            (add as fn(&mut State) -> Result<()>, "add", 3e38, 3e38),
+            (sub, "sub", -3e38, 3e38),
+            (mul, "mul", 1e38, 10.0),
+        ] {
+            let mut state = State::new();
+            state.operand_stack.push(a.into());
+            state.operand_stack.push(b.into());
+
+            assert_eq!(Err(PsError::Undefinedresult(name.into())), f(&mut state));
+        }
+    }
+
+    #[test]
+    fn division_by_zero_is_an_undefined_result() {
+        for (f, name) in [
+            (div as fn(&mut State) -> Result<()>, "div"),
+            (idiv, "idiv"),
+            (modulo, "mod"),
+        ] {
+            let mut state = State::new();
+            state.operand_stack.push(1.into());
+            state.operand_stack.push(0.into());
+
+            assert_eq!(Err(PsError::Undefinedresult(name.into())), f(&mut state));
+        }
+    }
+
+    #[test]
+    fn rounding_keeps_the_type() {
+        let mut state = State::new();
+        for n in [
+            Item::Number(3),
+            Item::Float(-2.5),
+            Item::Float(2.5),
+            Item::Float(-2.7),
+        ] {
+            for f in [ceiling, floor, round, truncate] {
+                state.operand_stack.push(n.clone());
+                f(&mut state).unwrap();
+            }
+        }
+
+        let mut expected = State::new();
+        for n in [3, 3, 3, 3] {
+            expected.operand_stack.push(Item::Number(n));
+        }
+        for n in [
+            -2.0, -3.0, -2.0, -2.0, 3.0, 2.0, 3.0, 2.0, -2.0, -3.0, -3.0, -2.0,
+        ] {
+            expected.operand_stack.push(Item::Float(n));
+        }
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn atan_returns_degrees_between_0_and_360() {
+        let mut state = State::new();
+        for (num, den) in [(0, 1), (1, 0), (-1, 0), (-1, -1)] {
+            state.operand_stack.push(num.into());
+            state.operand_stack.push(den.into());
+            atan(&mut state).unwrap();
+        }
+
+        let mut expected = State::new();
+        for n in [0.0, 90.0, 270.0, 225.0] {
+            expected.operand_stack.push(Item::Float(n));
+        }
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn transcendental_operators_check_their_domain() {
+        let mut state = State::new();
+        state.operand_stack.push(0.into());
+        state.operand_stack.push(0.into());
+        assert_eq!(
+            Err(PsError::Undefinedresult("atan".into())),
+            atan(&mut state)
+        );
+
+        state.operand_stack.push((-8).into());
+        state.operand_stack.push(0.5.into());
+        assert_eq!(Err(PsError::Undefinedresult("exp".into())), exp(&mut state));
+
+        state.operand_stack.push(0.into());
+        assert_eq!(Err(PsError::Rangecheck("ln".into())), ln(&mut state));
+
+        state.operand_stack.push((-1).into());
+        assert_eq!(Err(PsError::Rangecheck("log".into())), log(&mut state));
+    }
+
     #[test]
     fn sqrt_takes_the_square_root_of_the_top_element() {
         let mut state = State::new();
@@ -562,13 +1669,16 @@ mod test {
     #[test]
     fn def_creates_a_binding_in_the_current_dictionary() {
         let mut state = State::new();
-        state.operand_stack.push(Item::Key("foo".to_string()));
+        state.operand_stack.push(Item::Key("foo".into()));
         state.operand_stack.push(1.into());
 
         def(&mut state).unwrap();
 
-        let mut expected = State::new();
-        expected.dictionary.insert("foo".to_string(), 1.into());
+        let expected = State::new();
+        expected
+            .current_dict()
+            .borrow_mut()
+            .insert("foo".into(), 1.into());
 
         assert_eq!(state, expected);
     }
@@ -683,6 +1793,19 @@ mod test {
         assert_eq!(state, expected);
     }
 
+    #[test]
+    fn length_pushes_the_length_of_a_string_on_the_stack() {
+        let mut state = State::new();
+        state.operand_stack.push(Item::string(b"abc".to_vec()));
+
+        array_length(&mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(3.into());
+
+        assert_eq!(state, expected);
+    }
+
     #[test]
     fn convert_int_to_int() {
         let mut state = State::new();
@@ -708,4 +1831,124 @@ mod test {
 
         assert_eq!(state, expected);
     }
+
+    #[test]
+    fn token_scans_the_first_object_of_a_string() {
+        let mut state = State::new();
+        state
+            .operand_stack
+            .push(Item::string(b"  { 1 add } rest".to_vec()));
+
+        token(&mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::string(b" rest".to_vec()));
+        let block = vec![1.into(), Item::Name("add".into())];
+        expected.operand_stack.push(Item::Block(block.into()));
+        expected.operand_stack.push(true.into());
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn token_pushes_false_on_empty_strings() {
+        let mut state = State::new();
+        state
+            .operand_stack
+            .push(Item::string(b"  % nothing".to_vec()));
+
+        token(&mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(false.into());
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn operators_read_inline_data_from_currentfile() {
+        let mut state = State::new();
+
+        let code = "currentfile 3 string readstring\nabc currentfile token 42 \n";
+        super::super::execute(code, &mut state).unwrap();
+
+        let mut expected = State::new();
+        expected.operand_stack.push(Item::string(b"abc".to_vec()));
+        expected.operand_stack.push(true.into());
+        expected.operand_stack.push(42.into());
+        expected.operand_stack.push(true.into());
+
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn error_report_lists_the_stacks() {
+        let mut state = State::new();
+        state.operand_stack.push(Item::Number(1));
+        state.operand_stack.push(Item::Key("x".into()));
+        assert!(state.raise(&PsError::Typecheck("add".into()), 0));
+        state.operand_stack.push(Item::Key("typecheck".into()));
+        default_handler(&mut state).unwrap();
+
+        let expected = "\
+Error: /typecheck in --add--
+Operand stack:
+   1   /x
+Execution stack:
+
+Dictionary stack:
+   -dict-   -dict-   -dict-
+";
+        assert_eq!(expected, error_report(&state.error_info.borrow()));
+    }
+
+    #[test]
+    fn cvx_and_cvlit_toggle_the_executable_attribute() {
+        let mut state = State::new();
+        state.operand_stack.push(Item::Key("a".into()));
+        cvx(&mut state).unwrap();
+        assert_eq!(Item::Name("a".into()), state.operand_stack.pop().unwrap());
+
+        state.operand_stack.push(Item::Block(vec![1.into()].into()));
+        cvlit(&mut state).unwrap();
+        assert_eq!(
+            Item::from(vec![1.into()]),
+            state.operand_stack.pop().unwrap()
+        );
+
+        state.operand_stack.push(Item::string(b"1".to_vec()));
+        cvx(&mut state).unwrap();
+        let executable = state.operand_stack.pop().unwrap();
+        assert_eq!(
+            Item::Executable(Box::new(Item::string(b"1".to_vec()))),
+            executable
+        );
+
+        state.operand_stack.push(executable);
+        cvlit(&mut state).unwrap();
+        assert_eq!(
+            Item::string(b"1".to_vec()),
+            state.operand_stack.pop().unwrap()
+        );
+    }
+
+    #[test]
+    fn xcheck_tests_the_executable_attribute() {
+        let mut state = State::new();
+        for item in [
+            Item::Name("a".into()),
+            Item::Key("a".into()),
+            Item::Number(1).into_executable(),
+            Item::Number(1),
+        ] {
+            state.operand_stack.push(item);
+            xcheck(&mut state).unwrap();
+        }
+
+        let mut expected = State::new();
+        for b in [true, false, true, false] {
+            expected.operand_stack.push(b.into());
+        }
+        assert_eq!(expected, state);
+    }
 }
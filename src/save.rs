@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+
+use super::exec::Exec;
+use super::stack::{self, Access, Array, Block, Bytes, Composite, Dict, DictKey, Item};
+
+/// The changes to composite objects made since a `save`.
+///
+/// The contents of an object are recorded the first time it changes after the `save`.
+/// Restoring writes them back into the same objects, so all copies referring to them see
+/// the old values again.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Journal {
+    /// The id of the `save` object.
+    id: usize,
+    /// Objects with this serial number or above were created after the `save`.
+    serial: usize,
+    entries: Vec<Entry>,
+    /// The addresses of the objects in `entries`.
+    recorded: HashSet<usize>,
+}
+
+/// The contents of an object before its first change, with the rights of a dictionary.
+#[derive(Debug, PartialEq, Eq)]
+enum Entry {
+    Dict(Dict, HashMap<DictKey, Item>, Access),
+    Array(Array, Vec<Item>),
+    String(Bytes, Vec<u8>),
+}
+
+impl Journal {
+    /// Start recording changes for the `save` with id `id`.
+    pub fn new(id: usize) -> Self {
+        Journal {
+            id,
+            serial: stack::serial_mark(),
+            entries: Vec::new(),
+            recorded: HashSet::new(),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Whether `item` is a composite object created after the `save`.
+    pub fn is_newer(&self, item: &Item) -> bool {
+        item.serial().is_some_and(|serial| serial >= self.serial)
+    }
+
+    /// Record the contents of `item` before it changes, unless they were recorded already
+    /// or it was created after the `save`.
+    pub fn record(&mut self, item: &Item) {
+        if let Item::Executable(item) = item {
+            return self.record(item);
+        }
+        let Some(address) = item.address() else {
+            return;
+        };
+        if self.is_newer(item) || !self.recorded.insert(address) {
+            return;
+        }
+        let entry = match item {
+            Item::Dict(dict) => Entry::Dict(dict.clone(), dict.borrow().clone(), dict.access()),
+            Item::Array(items) | Item::Block(Block { items, .. }) => {
+                Entry::Array(items.clone(), items.borrow().clone())
+            }
+            Item::String(bytes) => Entry::String(bytes.clone(), bytes.borrow().clone()),
+            _ => unreachable!(),
+        };
+        self.entries.push(entry);
+    }
+
+    /// Put the recorded contents back into all recorded objects.
+    pub fn restore(self) {
+        for entry in self.entries {
+            match entry {
+                Entry::Dict(dict, entries, access) => dict.replace(entries, access),
+                Entry::Array(items, contents) => *items.borrow_mut() = contents,
+                Entry::String(bytes, contents) => *bytes.borrow_mut() = contents,
+            }
+        }
+    }
+}
+
+/// The composite objects an execution stack entry refers to.
+pub(crate) fn exec_items(exec: &Exec) -> Vec<Item> {
+    match exec {
+        Exec::File { pending, .. } => pending
+            .iter()
+            .flat_map(|proc| proc.items.iter().cloned())
+            .collect(),
+        Exec::Forall { items, proc, .. } => {
+            vec![Item::Array(items.clone()), Item::Block(proc.clone())]
+        }
+        Exec::ForallDict { entries, proc, .. } => entries
+            .iter()
+            .flat_map(|(key, value)| [key.clone().into_item(), value.clone()])
+            .chain([Item::Block(proc.clone())])
+            .collect(),
+        Exec::For {
+            control,
+            increment,
+            limit,
+            proc,
+        } => vec![
+            control.clone(),
+            increment.clone(),
+            limit.clone(),
+            Item::Block(proc.clone()),
+        ],
+        exec => vec![exec.to_item()],
+    }
+}
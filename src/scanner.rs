@@ -0,0 +1,597 @@
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::num::IntErrorKind;
+
+use super::dsc::DscComment;
+use super::error::{PsError, Result};
+use super::name::Name;
+use super::span::{Source, Span};
+use super::strings;
+
+macro_rules! bail {
+    ($kind:ident, $($rest:tt)+) => {
+        return Err(PsError::$kind(format!($($rest)+)))
+    }
+}
+
+/// How deeply `{`, `[` and `<<` may be nested in source code.
+///
+/// Objects nested much deeper would exhaust the native stack when they are compared,
+/// printed or dropped.
+const MAX_NESTING: usize = 1000;
+
+/// A single token of PostScript source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Integer(i32),
+    Real(f32),
+    /// An executable name, e.g. `add`, `[` or `]`.
+    Name(Name),
+    /// A literal name, e.g. `/add`.
+    LiteralName(Name),
+    /// An immediately evaluated name, e.g. `//add`.
+    ImmediateName(Name),
+    String(Vec<u8>),
+    ProcBegin,
+    ProcEnd,
+}
+
+pub(crate) fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n' | 0x0c | 0)
+}
+
+fn is_delimiter(c: u8) -> bool {
+    matches!(
+        c,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn is_regular(c: u8) -> bool {
+    !is_whitespace(c) && !is_delimiter(c)
+}
+
+/// Pulls tokens lazily from any reader.
+///
+/// See the PostScript Language Reference, section 3.2 for the syntax.
+pub struct Scanner {
+    reader: Box<dyn BufRead>,
+    source: Source,
+    /// Number of bytes consumed so far.
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// Line, column and offset where the last token started.
+    token_start: (usize, usize, usize),
+    /// Offset where the last token ended, before any whitespace consumed after it.
+    token_end: usize,
+    at_line_start: bool,
+    after_cr: bool,
+    /// Document structuring comments scanned so far, if they are collected.
+    dsc_comments: Option<Vec<DscComment>>,
+    /// The number of `{`, `[` and `<<` scanned but not closed yet.
+    nesting: usize,
+}
+
+impl Scanner {
+    pub fn new<R: Read + 'static>(reader: R, source: Source) -> Self {
+        Self::from_buf_read(BufReader::new(reader), source)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let source = Source::text("<string>", &bytes);
+        Self::from_buf_read(Cursor::new(bytes), source)
+    }
+
+    fn from_buf_read<R: BufRead + 'static>(reader: R, source: Source) -> Self {
+        Self {
+            reader: Box::new(reader),
+            source,
+            offset: 0,
+            line: 1,
+            column: 1,
+            token_start: (1, 1, 0),
+            token_end: 0,
+            at_line_start: true,
+            after_cr: false,
+            dsc_comments: None,
+            nesting: 0,
+        }
+    }
+
+    /// Number of bytes consumed from the reader so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The location of the last token scanned, or where scanning it failed.
+    pub fn last_span(&self) -> Span {
+        let (line, column, offset) = self.token_start;
+        Span {
+            source: self.source.clone(),
+            line,
+            column,
+            len: self.token_end.saturating_sub(offset),
+        }
+    }
+
+    /// Collect document structuring comments from now on, which only documents have.
+    pub fn collect_dsc_comments(&mut self) {
+        self.dsc_comments.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the document structuring comments scanned since the last call.
+    pub fn take_dsc_comments(&mut self) -> Vec<DscComment> {
+        self.dsc_comments
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    fn bump(&mut self) {
+        if let Ok(&[c, ..]) = self.reader.fill_buf() {
+            self.reader.consume(1);
+            self.offset += 1;
+            self.column += 1;
+            match c {
+                b'\r' => self.line += 1,
+                b'\n' if !self.after_cr => self.line += 1,
+                _ => (),
+            }
+            if matches!(c, b'\r' | b'\n') {
+                self.column = 1;
+            }
+            self.at_line_start = matches!(c, b'\r' | b'\n');
+            self.after_cr = c == b'\r';
+        }
+    }
+
+    /// Read a single byte, bypassing the tokenizer.
+    pub fn read_byte(&mut self) -> Result<Option<u8>> {
+        let c = self.peek()?;
+        self.bump();
+        Ok(c)
+    }
+
+    /// Read up to the next end-of-line marker, which is consumed but not returned.
+    ///
+    /// The flag is `false` if the end of the input was reached before an end-of-line marker.
+    pub fn read_line(&mut self) -> Result<(Vec<u8>, bool)> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte()? {
+                None => return Ok((line, false)),
+                Some(b'\n') => break,
+                Some(b'\r') => {
+                    if self.peek()? == Some(b'\n') {
+                        self.bump();
+                    }
+                    break;
+                }
+                Some(c) => line.push(c),
+            }
+        }
+        Ok((line, true))
+    }
+
+    /// Scan the next token, or `None` at the end of the input.
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        loop {
+            match self.peek()? {
+                None => return Ok(None),
+                Some(c) if is_whitespace(c) => self.bump(),
+                Some(b'%') => self.comment()?,
+                Some(c) => {
+                    self.token_start = (self.line, self.column, self.offset);
+                    self.token_end = usize::MAX;
+                    let token = self.token(c);
+                    self.token_end = self.token_end.min(self.offset);
+                    let token = token?;
+                    self.nest(&token)?;
+                    return Ok(Some(token));
+                }
+            }
+        }
+    }
+
+    fn token(&mut self, c: u8) -> Result<Token> {
+        match c {
+            b'(' => {
+                self.bump();
+                self.literal_string()
+            }
+            b'<' => {
+                self.bump();
+                if self.peek()? == Some(b'<') {
+                    self.bump();
+                    return Ok(Token::Name("<<".into()));
+                }
+                self.hex_or_base85_string()
+            }
+
+            b'{' => {
+                self.bump();
+                Ok(Token::ProcBegin)
+            }
+            b'}' => {
+                self.bump();
+                Ok(Token::ProcEnd)
+            }
+            b'[' | b']' => {
+                self.bump();
+                Ok(Token::Name([c][..].into()))
+            }
+            b'/' => {
+                self.bump();
+                if self.peek()? == Some(b'/') {
+                    self.bump();
+                    let name = self.regular_run()?;
+                    return Ok(Token::ImmediateName(name));
+                }
+                let name = self.regular_run()?;
+                Ok(Token::LiteralName(name))
+            }
+            b')' | b'>' => {
+                self.bump();
+                if c == b'>' && self.peek()? == Some(b'>') {
+                    self.bump();
+                    return Ok(Token::Name(">>".into()));
+                }
+                bail!(Syntaxerror, "{}", c as char);
+            }
+            _ => {
+                let run = self.regular_run()?;
+                let number = match std::str::from_utf8(run.as_bytes()) {
+                    Ok(text) => parse_number(text)?,
+                    Err(_) => None,
+                };
+                Ok(number.unwrap_or(Token::Name(run)))
+            }
+        }
+    }
+
+    /// Keep track of how deeply `token` is nested. Raises `/limitcheck` when it is nested
+    /// deeper than `MAX_NESTING`.
+    fn nest(&mut self, token: &Token) -> Result<()> {
+        let opening = match token {
+            Token::ProcBegin => "{",
+            Token::Name(name) if *name == "[" => "[",
+            Token::Name(name) if *name == "<<" => "<<",
+            Token::ProcEnd => {
+                self.nesting = self.nesting.saturating_sub(1);
+                return Ok(());
+            }
+            Token::Name(name) if *name == "]" || *name == ">>" => {
+                self.nesting = self.nesting.saturating_sub(1);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        if self.nesting == MAX_NESTING {
+            bail!(Limitcheck, "{}", opening);
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    fn comment(&mut self) -> Result<()> {
+        let at_line_start = self.at_line_start;
+        let line = self.line;
+        self.bump();
+
+        let mut text = Vec::new();
+        while let Some(c) = self.peek()? {
+            if matches!(c, b'\r' | b'\n') {
+                break;
+            }
+            text.push(c);
+            self.bump();
+        }
+
+        if let Some(comments) = &mut self.dsc_comments {
+            if at_line_start && text.first() == Some(&b'%') {
+                let text = String::from_utf8_lossy(&text[1..]);
+                comments.push(DscComment::parse(line, &text));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a run of regular characters. The whitespace character ending it is consumed.
+    fn regular_run(&mut self) -> Result<Name> {
+        let mut run = Vec::new();
+        while let Some(c) = self.peek()? {
+            if !is_regular(c) {
+                break;
+            }
+            run.push(c);
+            self.bump();
+        }
+
+        self.token_end = self.offset;
+        match self.peek()? {
+            Some(b'\r') => {
+                self.bump();
+                if self.peek()? == Some(b'\n') {
+                    self.bump();
+                }
+            }
+            Some(c) if is_whitespace(c) => self.bump(),
+            _ => (),
+        }
+
+        Ok(run.into())
+    }
+
+    fn literal_string(&mut self) -> Result<Token> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let c = match self.read_byte()? {
+                Some(c) => c,
+                None => bail!(Syntaxerror, "("),
+            };
+            match c {
+                b'(' => depth += 1,
+                b')' if depth == 0 => break,
+                b')' => depth -= 1,
+                b'\\' => {
+                    body.push(c);
+                    match self.read_byte()? {
+                        Some(c) => body.push(c),
+                        None => bail!(Syntaxerror, "("),
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+            body.push(c);
+        }
+
+        Ok(Token::String(strings::decode_literal(&body)))
+    }
+
+    fn hex_or_base85_string(&mut self) -> Result<Token> {
+        let base85 = self.peek()? == Some(b'~');
+        if base85 {
+            self.bump();
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match self.read_byte()? {
+                Some(b'>') if !base85 => break,
+                Some(b'~') if base85 && self.peek()? == Some(b'>') => {
+                    self.bump();
+                    break;
+                }
+                Some(c) => body.push(c),
+                None if base85 => bail!(Syntaxerror, "<~"),
+                None => bail!(Syntaxerror, "<"),
+            }
+        }
+
+        let decoded = if base85 {
+            strings::decode_base85(&body)
+        } else {
+            strings::decode_hex(&body)
+        };
+        match decoded {
+            Some(s) => Ok(Token::String(s)),
+            None if base85 => bail!(Syntaxerror, "<~{}~>", String::from_utf8_lossy(&body)),
+            None => bail!(Syntaxerror, "<{}>", String::from_utf8_lossy(&body)),
+        }
+    }
+}
+
+/// Parse a run of regular characters as a number, if it is one.
+fn parse_number(text: &str) -> Result<Option<Token>> {
+    if let Some(pos) = text.find('#') {
+        return parse_radix_number(text, pos);
+    }
+
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(pos) => (&unsigned[..pos], Some(&unsigned[pos + 1..])),
+        None => (unsigned, None),
+    };
+    let (int_part, frac_part) = match mantissa.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+        None => (mantissa, None),
+    };
+
+    let all_digits = |s: &str| s.bytes().all(|c| c.is_ascii_digit());
+    if !all_digits(int_part) || !frac_part.is_none_or(all_digits) {
+        return Ok(None);
+    }
+    if int_part.is_empty() && frac_part.is_none_or(str::is_empty) {
+        return Ok(None);
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.is_empty() || !all_digits(digits) {
+            return Ok(None);
+        }
+    }
+
+    if frac_part.is_none() && exponent.is_none() {
+        if let Ok(n) = text.parse() {
+            return Ok(Some(Token::Integer(n)));
+        }
+    }
+
+    // Integers exceeding the integer range become reals.
+    match text.parse::<f32>() {
+        Ok(n) if n.is_finite() => Ok(Some(Token::Real(n))),
+        _ => bail!(Limitcheck, "{}", text),
+    }
+}
+
+/// Parse `base#digits`. Radix numbers are unsigned 32-bit values, e.g. `16#FFFFFFFF` is -1.
+fn parse_radix_number(text: &str, pos: usize) -> Result<Option<Token>> {
+    let radix = match text[..pos].parse() {
+        Ok(radix) if pos <= 2 && (2..=36).contains(&radix) => radix,
+        _ => return Ok(None),
+    };
+    let digits = &text[pos + 1..];
+    if !digits.bytes().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(None);
+    }
+
+    match u32::from_str_radix(digits, radix) {
+        Ok(number) => Ok(Some(Token::Integer(number as i32))),
+        Err(e) if *e.kind() == IntErrorKind::PosOverflow => bail!(Limitcheck, "{}", text),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(code: &str) -> Vec<Token> {
+        let mut scanner = Scanner::from_bytes(code.as_bytes().to_vec());
+        let mut tokens = vec![];
+        while let Some(token) = scanner.next_token().unwrap() {
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    #[test]
+    fn scans_numbers() {
+        assert_eq!(
+            vec![
+                Token::Integer(1),
+                Token::Integer(-2),
+                Token::Real(0.5),
+                Token::Real(5.0),
+                Token::Real(-3.2e-4),
+                Token::Integer(255),
+                Token::Real(3e9),
+            ],
+            tokens("1 -2 .5 5. -3.2E-4 16#FF 3000000000")
+        );
+    }
+
+    #[test]
+    fn scans_almost_numbers_as_names() {
+        assert_eq!(
+            vec![
+                Token::Name("1a".into()),
+                Token::Name("1e".into()),
+                Token::Name(".".into()),
+                Token::Name("+".into()),
+                Token::Name("8#9".into()),
+                Token::Name("1.2.3".into()),
+            ],
+            tokens("1a 1e . + 8#9 1.2.3")
+        );
+    }
+
+    #[test]
+    fn scans_names_and_delimiters() {
+        assert_eq!(
+            vec![
+                Token::LiteralName("a".into()),
+                Token::ProcBegin,
+                Token::Integer(1),
+                Token::ProcEnd,
+                Token::Name("def".into()),
+                Token::Name("[".into()),
+                Token::ImmediateName("x".into()),
+                Token::Name("]".into()),
+                Token::LiteralName("".into()),
+            ],
+            tokens("/a{1}def[//x]/")
+        );
+        assert_eq!(
+            vec![
+                Token::Name("<<".into()),
+                Token::LiteralName("a".into()),
+                Token::String(b"AB".to_vec()),
+                Token::Name(">>".into()),
+            ],
+            tokens("<</a<4142>>>")
+        );
+    }
+
+    #[test]
+    fn keeps_the_bytes_of_names() {
+        let mut scanner = Scanner::from_bytes(vec![b'/', 0xff, b' ', 0xfe]);
+        let mut tokens = vec![];
+        while let Some(token) = scanner.next_token().unwrap() {
+            tokens.push(token);
+        }
+        assert_eq!(
+            vec![
+                Token::LiteralName([0xff][..].into()),
+                Token::Name([0xfe][..].into()),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn scans_strings() {
+        assert_eq!(
+            vec![
+                Token::String(b"a (b)".to_vec()),
+                Token::String(b"AB".to_vec()),
+                Token::String(b"Hello".to_vec()),
+            ],
+            tokens("(a (b))<4142><~87cURDZ~>")
+        );
+    }
+
+    #[test]
+    fn collects_dsc_comments_at_line_start() {
+        let mut scanner = Scanner::from_bytes(b"%!PS\r\n%%Pages: 1\r\n1 %%Page: 1 1\n".to_vec());
+        scanner.collect_dsc_comments();
+        while scanner.next_token().unwrap().is_some() {}
+
+        let comments = scanner.take_dsc_comments();
+        assert_eq!(1, comments.len());
+        assert_eq!(2, comments[0].line);
+        assert_eq!("Pages", comments[0].keyword);
+    }
+
+    #[test]
+    fn consumes_one_whitespace_after_a_name() {
+        let mut scanner = Scanner::from_bytes(b"name  rest".to_vec());
+        scanner.next_token().unwrap();
+        assert_eq!(5, scanner.offset());
+        assert_eq!(Some(b' '), scanner.read_byte().unwrap());
+    }
+
+    #[test]
+    fn limits_how_deeply_brackets_are_nested() {
+        for open in ["{", "[", "<<"] {
+            let code = open.repeat(MAX_NESTING) + "}] >>";
+            let mut scanner = Scanner::from_bytes(code.into_bytes());
+            while scanner.next_token().unwrap().is_some() {}
+
+            let mut scanner = Scanner::from_bytes(open.repeat(MAX_NESTING + 1).into_bytes());
+            let err = loop {
+                if let Err(e) = scanner.next_token() {
+                    break e;
+                }
+            };
+            assert_eq!(PsError::Limitcheck(open.into()), err);
+        }
+    }
+
+    #[test]
+    fn tracks_token_spans() {
+        let mut scanner = Scanner::from_bytes(b"1 add\r\n  (a\nb) %c\n  foo".to_vec());
+        let mut spans = vec![];
+        while scanner.next_token().unwrap().is_some() {
+            let span = scanner.last_span();
+            spans.push((span.line, span.column, span.len));
+        }
+        assert_eq!(vec![(1, 1, 1), (1, 3, 3), (2, 3, 5), (4, 3, 3)], spans);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug)]
+enum Origin {
+    /// Source held in memory.
+    Text(Arc<[u8]>),
+    /// Source read from a file, which is opened again to show snippets.
+    Path(PathBuf),
+    /// Source read from a stream, which can't be shown again.
+    Stream,
+}
+
+/// Where source code came from.
+#[derive(Debug, Clone)]
+pub struct Source {
+    name: Arc<str>,
+    origin: Arc<Origin>,
+}
+
+impl Source {
+    pub fn text(name: &str, text: &[u8]) -> Self {
+        Self::new(name, Origin::Text(text.into()))
+    }
+
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(&path.display().to_string(), Origin::Path(path))
+    }
+
+    pub fn stream(name: &str) -> Self {
+        Self::new(name, Origin::Stream)
+    }
+
+    fn new(name: &str, origin: Origin) -> Self {
+        Source {
+            name: name.into(),
+            origin: Arc::new(origin),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The text of the 1-based `line`, if it can still be read.
+    pub fn line(&self, line: usize) -> Option<String> {
+        let text = self.line_bytes(line)?;
+        Some(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    /// The bytes of the 1-based `line`, without its end-of-line marker.
+    fn line_bytes(&self, line: usize) -> Option<Vec<u8>> {
+        let index = line.checked_sub(1)?;
+        match &*self.origin {
+            Origin::Text(text) => lines(text).nth(index).map(<[u8]>::to_vec),
+            Origin::Path(path) => lines(&fs::read(path).ok()?).nth(index).map(<[u8]>::to_vec),
+            Origin::Stream => None,
+        }
+    }
+}
+
+/// Split `text` into lines the way the scanner counts them, at `\r\n`, `\r` or `\n`.
+fn lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = Some(text);
+    std::iter::from_fn(move || {
+        let text = rest?;
+        match text.iter().position(|&c| matches!(c, b'\r' | b'\n')) {
+            Some(end) => {
+                let marker = if text[end..].starts_with(b"\r\n") {
+                    2
+                } else {
+                    1
+                };
+                rest = Some(&text[end + marker..]);
+                Some(&text[..end])
+            }
+            None => {
+                rest = None;
+                Some(text)
+            }
+        }
+    })
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.origin, &other.origin)
+    }
+}
+
+impl Eq for Source {}
+
+/// The location of a token in its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub source: Source,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in bytes.
+    pub column: usize,
+    /// Length of the token in bytes, if it doesn't span multiple lines.
+    pub len: usize,
+}
+
+impl Span {
+    /// The text of the line the span starts on, with the column and width of the span
+    /// counted in characters of that text rather than bytes.
+    pub fn snippet(&self) -> Option<(String, usize, usize)> {
+        let text = self.source.line_bytes(self.line)?;
+        let start = (self.column - 1).min(text.len());
+        let end = (start + self.len).min(text.len());
+        let width = |bytes: &[u8]| String::from_utf8_lossy(bytes).chars().count();
+        Some((
+            String::from_utf8_lossy(&text).into_owned(),
+            width(&text[..start]),
+            width(&text[start..end]),
+        ))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name(), self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_sources_return_lines() {
+        let source = Source::text("test", b"first\r\nsecond\nthird");
+        assert_eq!(Some("first".to_string()), source.line(1));
+        assert_eq!(Some("third".to_string()), source.line(3));
+        assert_eq!(None, source.line(4));
+        assert_eq!(None, source.line(0));
+    }
+
+    #[test]
+    fn lines_end_at_cr_lf_or_both() {
+        let source = Source::text("test", b"a\rb\r\nc\nd");
+        assert_eq!(Some("b".to_string()), source.line(2));
+        assert_eq!(Some("c".to_string()), source.line(3));
+        assert_eq!(Some("d".to_string()), source.line(4));
+    }
+
+    #[test]
+    fn snippets_count_columns_in_characters() {
+        let span = Span {
+            source: Source::text("test", "\u{e6}\u{f8} abc".as_bytes()),
+            line: 1,
+            column: 6,
+            len: 3,
+        };
+        assert_eq!(Some(("\u{e6}\u{f8} abc".to_string(), 3, 3)), span.snippet());
+    }
+
+    #[test]
+    fn stream_sources_have_no_lines() {
+        let source = Source::stream("stdin");
+        assert_eq!(None, source.line(1));
+    }
+}
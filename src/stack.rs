@@ -1,82 +1,719 @@
-use color_eyre::eyre::{Report, Result};
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-macro_rules! msg {
-    ($($rest:tt)+) => {
-        Err(Report::msg(format!($($rest)+)))
+use super::error::{PsError, Result};
+use super::file::File;
+use super::name::Name;
+use super::operators::Operator;
+use super::span::Span;
+
+fn typecheck<T>() -> Result<T> {
+    Err(PsError::Typecheck(String::new()))
+}
+
+/// The entries of a dictionary, shared by all objects referring to it.
+pub type Dict = Rc<Dictionary>;
+
+/// The elements of an array or procedure, shared by all objects referring to it.
+pub type Array = Shared<Vec<Item>>;
+
+/// The bytes of a string, shared by all objects referring to it.
+pub type Bytes = Shared<Vec<u8>>;
+
+/// The access rights to a composite object, from least to most permissive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    None,
+    ExecuteOnly,
+    ReadOnly,
+    #[default]
+    Unlimited,
+}
+
+impl Access {
+    /// Raise `/invalidaccess` unless these rights include `required`.
+    fn require(self, required: Access) -> Result<()> {
+        if self < required {
+            return Err(PsError::Invalidaccess(String::new()));
+        }
+        Ok(())
+    }
+
+    /// These rights reduced to `access`. Raises `/invalidaccess` if that would increase them.
+    fn reduce(self, access: Access) -> Result<Access> {
+        self.require(access)?;
+        Ok(access)
+    }
+}
+
+/// The serial number of the next composite object.
+static NEXT_SERIAL: AtomicUsize = AtomicUsize::new(0);
+
+/// A serial number for a new composite object, larger than those of all earlier ones.
+fn next_serial() -> usize {
+    NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The serial number the next composite object will get, so objects with this one or
+/// above are created after now.
+pub(crate) fn serial_mark() -> usize {
+    NEXT_SERIAL.load(Ordering::Relaxed)
+}
+
+/// A composite object: a dictionary, array, procedure or string.
+pub trait Composite {
+    /// The access rights to the contents through this object.
+    fn access(&self) -> Access;
+
+    /// Tells objects created earlier from those created later.
+    fn serial(&self) -> usize;
+
+    /// Raise `/invalidaccess` unless the contents may be read.
+    fn check_read(&self) -> Result<()> {
+        self.access().require(Access::ReadOnly)
+    }
+
+    /// Raise `/invalidaccess` unless the contents may be changed.
+    fn check_write(&self) -> Result<()> {
+        self.access().require(Access::Unlimited)
+    }
+
+    /// Raise `/invalidaccess` unless the object may be executed.
+    fn check_execute(&self) -> Result<()> {
+        self.access().require(Access::ExecuteOnly)
     }
 }
 
+/// The entries of a dictionary with its access rights.
+///
+/// The rights are kept with the entries, so restricting them affects all copies of the
+/// dictionary.
+pub struct Dictionary {
+    entries: RefCell<HashMap<DictKey, Item>>,
+    access: Cell<Access>,
+    serial: usize,
+    /// The number of entries the dictionary was created for, see `max_length`.
+    max_length: usize,
+}
+
+impl Dictionary {
+    pub fn new(entries: HashMap<DictKey, Item>) -> Self {
+        Dictionary::with_access(entries, Access::Unlimited)
+    }
+
+    pub fn with_access(entries: HashMap<DictKey, Item>, access: Access) -> Self {
+        Dictionary {
+            entries: RefCell::new(entries),
+            access: Cell::new(access),
+            serial: next_serial(),
+            max_length: 0,
+        }
+    }
+
+    /// A dictionary created for `max_length` entries, as `dict` makes them.
+    pub fn with_max_length(entries: HashMap<DictKey, Item>, max_length: usize) -> Self {
+        Dictionary {
+            max_length,
+            ..Dictionary::new(entries)
+        }
+    }
+
+    /// Put back entries and access rights recorded earlier, for `restore`.
+    pub(crate) fn replace(&self, entries: HashMap<DictKey, Item>, access: Access) {
+        *self.entries.borrow_mut() = entries;
+        self.access.set(access);
+    }
+
+    /// Reduce the access rights of all copies. Raises `/invalidaccess` when trying to
+    /// increase them.
+    pub fn restrict(&self, access: Access) -> Result<()> {
+        self.access.set(self.access.get().reduce(access)?);
+        Ok(())
+    }
+
+    /// The number of entries the dictionary was created for, or the number it holds if
+    /// it has grown beyond that.
+    pub fn max_length(&self) -> usize {
+        self.max_length.max(self.entries.borrow().len())
+    }
+}
+
+impl Composite for Dictionary {
+    fn access(&self) -> Access {
+        self.access.get()
+    }
+
+    fn serial(&self) -> usize {
+        self.serial
+    }
+}
+
+impl Default for Dictionary {
+    fn default() -> Self {
+        Dictionary::new(HashMap::new())
+    }
+}
+
+/// Dictionaries are equal if their entries and access rights are, regardless of their age.
+impl PartialEq for Dictionary {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries && self.access == other.access
+    }
+}
+
+impl Eq for Dictionary {}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.entries.as_ptr() as *const () as usize;
+        fmt_once(address, f, "...", |f| {
+            f.debug_struct("Dictionary")
+                .field("entries", &self.entries)
+                .field("access", &self.access)
+                .finish()
+        })
+    }
+}
+
+thread_local! {
+    /// The addresses of the composite objects being formatted.
+    static FORMATTING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// Format the composite object at `address` with `fmt`, or write `cycle` if it is
+/// already being formatted, so objects containing themselves don't recurse forever.
+fn fmt_once(
+    address: usize,
+    f: &mut fmt::Formatter<'_>,
+    cycle: &str,
+    fmt: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    if !FORMATTING.with(|formatting| formatting.borrow_mut().insert(address)) {
+        return f.write_str(cycle);
+    }
+    let result = fmt(f);
+    FORMATTING.with(|formatting| formatting.borrow_mut().remove(&address));
+    result
+}
+
+impl Deref for Dictionary {
+    type Target = RefCell<HashMap<DictKey, Item>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+/// A reference to the contents of an array, procedure or string, with its access rights.
+///
+/// Copies share the contents, but each has its own rights, so restricting them leaves
+/// other copies of the object as they were.
+pub struct Shared<T> {
+    value: Rc<RefCell<T>>,
+    access: Access,
+    serial: usize,
+}
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Shared {
+            value: Rc::new(RefCell::new(value)),
+            access: Access::Unlimited,
+            serial: next_serial(),
+        }
+    }
+
+    /// Whether both refer to the same contents.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Rc::ptr_eq(&a.value, &b.value)
+    }
+
+    /// Reduce the access rights of this reference. Raises `/invalidaccess` when trying to
+    /// increase them.
+    pub fn restrict(&mut self, access: Access) -> Result<()> {
+        self.access = self.access.reduce(access)?;
+        Ok(())
+    }
+}
+
+impl<T> Composite for Shared<T> {
+    fn access(&self) -> Access {
+        self.access
+    }
+
+    fn serial(&self) -> usize {
+        self.serial
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared {
+            value: self.value.clone(),
+            access: self.access,
+            serial: self.serial,
+        }
+    }
+}
+
+/// Objects are equal if their contents and access rights are, regardless of their age.
+impl<T: PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.access == other.access
+    }
+}
+
+impl<T: Eq> Eq for Shared<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.value.as_ptr() as *const () as usize;
+        fmt_once(address, f, "...", |f| {
+            f.debug_struct("Shared")
+                .field("value", &self.value)
+                .field("access", &self.access)
+                .finish()
+        })
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = RefCell<T>;
+
+    fn deref(&self) -> &RefCell<T> {
+        &self.value
+    }
+}
+
+/// A PostScript object.
+///
+/// Copies of composite objects (dictionaries, arrays, procedures and strings)
+/// share their contents. Comparing items compares the contents, while `eq`
+/// compares their identity.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Item {
     Number(i32),
     Float(f32),
     Bool(bool),
-    Dict(HashMap<String, Item>),
-    Key(String),
-    Block(String),
+    Dict(Dict),
+    Key(Name),
+    Name(Name),
+    String(Bytes),
+    Block(Block),
     Mark,
-    Array(Vec<Item>),
+    Array(Array),
+    File(File),
+    /// An executable object of a type without an executable variant of its own,
+    /// e.g. an executable string.
+    ///
+    /// Names and arrays use `Name` and `Block` instead and are never wrapped.
+    Executable(Box<Item>),
+    Operator(Operator),
+    /// The state saved by `save`, identified by its id.
+    Save(usize),
 }
 
 impl Eq for Item {}
 
 impl Item {
+    /// A new string object.
+    pub fn string(bytes: Vec<u8>) -> Item {
+        Item::String(Shared::new(bytes))
+    }
+
+    /// The object without its executable attribute, for operators accepting either.
+    pub(crate) fn literal(&self) -> &Item {
+        match self {
+            Item::Executable(item) => item,
+            item => item,
+        }
+    }
+
+    /// Whether the object is executable.
+    pub fn is_executable(&self) -> bool {
+        matches!(
+            self,
+            Item::Name(_) | Item::Block(_) | Item::Executable(_) | Item::Operator(_)
+        )
+    }
+
+    /// The object with the executable attribute set, as `cvx` returns it.
+    pub fn into_executable(self) -> Item {
+        match self {
+            Item::Key(name) => Item::Name(name),
+            Item::Array(items) => Item::Block(Block {
+                items,
+                spans: Rc::new([]),
+            }),
+            item @ (Item::Name(_) | Item::Block(_) | Item::Executable(_) | Item::Operator(_)) => {
+                item
+            }
+            item => Item::Executable(Box::new(item)),
+        }
+    }
+
+    /// The object with the literal attribute set, as `cvlit` returns it.
+    pub fn into_literal(self) -> Item {
+        match self {
+            Item::Name(name) => Item::Key(name),
+            Item::Block(block) => Item::Array(block.items),
+            Item::Executable(item) => *item,
+            item => item,
+        }
+    }
+
+    /// Compare two objects the way `eq` does.
+    ///
+    /// Numbers compare by value regardless of their type, strings and names by their text.
+    /// Arrays, procedures and dictionaries are only equal to the same object.
+    pub fn equals(&self, other: &Item) -> bool {
+        fn text(item: &Item) -> Option<Vec<u8>> {
+            match item {
+                Item::Key(name) | Item::Name(name) => Some(name.as_bytes().to_vec()),
+                Item::String(s) => Some(s.borrow().clone()),
+                _ => None,
+            }
+        }
+
+        fn array(item: &Item) -> Option<&Array> {
+            match item {
+                Item::Array(items) | Item::Block(Block { items, .. }) => Some(items),
+                _ => None,
+            }
+        }
+
+        let (a, b) = (self.literal(), other.literal());
+        match (a, b) {
+            (Item::Number(a), Item::Number(b)) => a == b,
+            (Item::Number(_) | Item::Float(_), Item::Number(_) | Item::Float(_)) => {
+                a.as_float().ok() == b.as_float().ok()
+            }
+            (Item::Dict(a), Item::Dict(b)) => Rc::ptr_eq(a, b),
+            _ => match (array(a), array(b), text(a), text(b)) {
+                (Some(a), Some(b), _, _) => Shared::ptr_eq(a, b),
+                (_, _, Some(a), Some(b)) => a == b,
+                _ => a == b,
+            },
+        }
+    }
+
+    /// The address of the contents of a composite object, which identifies it.
+    pub(crate) fn address(&self) -> Option<usize> {
+        match self.literal() {
+            Item::Dict(dict) => Some(dict.as_ptr() as *const () as usize),
+            Item::Array(items) | Item::Block(Block { items, .. }) => {
+                Some(items.as_ptr() as *const () as usize)
+            }
+            Item::String(bytes) => Some(bytes.as_ptr() as *const () as usize),
+            _ => None,
+        }
+    }
+
+    /// The serial number of a composite object, which is larger for objects created later.
+    pub(crate) fn serial(&self) -> Option<usize> {
+        match self.literal() {
+            Item::Dict(dict) => Some(dict.serial()),
+            Item::Array(items) | Item::Block(Block { items, .. }) => Some(items.serial()),
+            Item::String(bytes) => Some(bytes.serial()),
+            _ => None,
+        }
+    }
+
+    /// The access rights of a composite object.
+    pub fn access(&self) -> Result<Access> {
+        match self.literal() {
+            Item::Dict(dict) => Ok(dict.access()),
+            Item::Array(items) | Item::Block(Block { items, .. }) => Ok(items.access()),
+            Item::String(bytes) => Ok(bytes.access()),
+            _ => typecheck(),
+        }
+    }
+
+    /// Reduce the access rights of a composite object, as `readonly` and friends do.
+    ///
+    /// This affects all copies of a dictionary, but only this copy of other objects.
+    pub fn restrict(&mut self, access: Access) -> Result<()> {
+        match self {
+            Item::Executable(item) => item.restrict(access),
+            Item::Dict(dict) => dict.restrict(access),
+            Item::Array(items) | Item::Block(Block { items, .. }) => items.restrict(access),
+            Item::String(bytes) => bytes.restrict(access),
+            _ => typecheck(),
+        }
+    }
+
     pub fn as_int(&self) -> Result<i32> {
-        if let &Item::Number(i) = self {
+        if let &Item::Number(i) = self.literal() {
             Ok(i)
         } else {
-            msg!("{:?} not an int", self)
+            typecheck()
         }
     }
 
     pub fn as_float(&self) -> Result<f32> {
-        match *self {
+        match *self.literal() {
             Item::Number(i) => Ok(i as f32),
             Item::Float(f) => Ok(f),
-            _ => msg!("{:?} not a float", self),
+            _ => typecheck(),
         }
     }
 
-    pub fn as_key(&self) -> Result<&str> {
+    pub fn as_key(&self) -> Result<&Name> {
         if let Item::Key(s) = self {
             Ok(s)
         } else {
-            msg!("{:?} not a key", self)
+            typecheck()
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&Bytes> {
+        if let Item::String(s) = self.literal() {
+            Ok(s)
+        } else {
+            typecheck()
+        }
+    }
+
+    pub fn as_file(&self) -> Result<&File> {
+        if let Item::File(file) = self.literal() {
+            Ok(file)
+        } else {
+            typecheck()
         }
     }
 
-    pub fn as_block(&self) -> Result<&str> {
+    pub fn as_block(&self) -> Result<&Block> {
         if let Item::Block(s) = self {
             Ok(s)
         } else {
-            msg!("{:?} not a block", self)
+            typecheck()
         }
     }
 
-    pub fn as_array(&self) -> Result<&[Item]> {
+    pub fn as_array(&self) -> Result<&Array> {
         if let Item::Array(a) = self {
             Ok(a)
         } else {
-            panic!("{:?} not an array", self);
+            typecheck()
         }
     }
 
     pub fn as_bool(&self) -> Result<bool> {
-        if let &Item::Bool(b) = self {
+        if let &Item::Bool(b) = self.literal() {
             Ok(b)
         } else {
-            panic!("{:?} not a bool", self);
+            typecheck()
         }
     }
 
-    pub fn into_dict(self) -> Result<HashMap<String, Item>> {
-        if let Item::Dict(d) = self {
-            Ok(d)
-        } else {
-            panic!("{:?} not a dict", self);
+    pub fn into_dict(self) -> Result<Dict> {
+        match self {
+            Item::Dict(d) => Ok(d),
+            Item::Executable(item) => item.into_dict(),
+            _ => typecheck(),
+        }
+    }
+}
+
+/// Formats an item the way `==` prints it.
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Number(n) => write!(f, "{}", n),
+            Item::Float(n) => write!(f, "{:?}", n),
+            Item::Bool(b) => write!(f, "{}", b),
+            Item::Dict(_) => write!(f, "-dict-"),
+            Item::Key(k) => write!(f, "/{}", k),
+            Item::Name(n) => write!(f, "{}", n),
+            Item::String(s) => fmt_string(&s.borrow(), f),
+            Item::Block(b) => fmt_once(self.address().unwrap(), f, "{...}", |f| {
+                write!(f, "{{")?;
+                for (i, item) in b.items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "}}")
+            }),
+            Item::Mark => write!(f, "-mark-"),
+            Item::Array(a) => fmt_once(self.address().unwrap(), f, "[...]", |f| {
+                write!(f, "[")?;
+                for (i, item) in a.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }),
+            Item::File(_) => write!(f, "-file-"),
+            Item::Executable(item) => write!(f, "{}", item),
+            Item::Operator(op) => write!(f, "--{}--", op.name()),
+            Item::Save(_) => write!(f, "-save-"),
+        }
+    }
+}
+
+/// Formats a string as a literal string with all special characters escaped.
+fn fmt_string(s: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "(")?;
+    for &c in s {
+        match c {
+            b'(' | b')' | b'\\' => write!(f, "\\{}", c as char)?,
+            b'\n' => write!(f, "\\n")?,
+            b'\r' => write!(f, "\\r")?,
+            b'\t' => write!(f, "\\t")?,
+            0x20..=0x7e => write!(f, "{}", c as char)?,
+            c => write!(f, "\\{:03o}", c)?,
         }
     }
+    write!(f, ")")
+}
+
+/// A dictionary key.
+///
+/// Strings are converted to names and integral reals to integers, so `(a)` and `/a`
+/// or `1` and `1.0` refer to the same entry. Other objects compare by identity, like `eq`.
+#[derive(Debug, Clone)]
+pub enum DictKey {
+    Name(Name),
+    Number(i32),
+    /// A real that isn't an integer, by its bits.
+    Float(u32),
+    Bool(bool),
+    Object(Item),
+}
+
+impl DictKey {
+    /// The object to push for this key, e.g. in `forall`.
+    pub fn into_item(self) -> Item {
+        match self {
+            DictKey::Name(name) => Item::Key(name),
+            DictKey::Number(n) => Item::Number(n),
+            DictKey::Float(bits) => Item::Float(f32::from_bits(bits)),
+            DictKey::Bool(b) => Item::Bool(b),
+            DictKey::Object(item) => item,
+        }
+    }
+}
+
+impl From<Item> for DictKey {
+    fn from(item: Item) -> Self {
+        match item {
+            Item::Key(name) | Item::Name(name) => DictKey::Name(name),
+            Item::String(s) => DictKey::Name(s.borrow().clone().into()),
+            Item::Number(n) => DictKey::Number(n),
+            Item::Float(f) if f.fract() == 0.0 && f >= i32::MIN as f32 && f < i32::MAX as f32 => {
+                DictKey::Number(f as i32)
+            }
+            Item::Float(f) => DictKey::Float(f.to_bits()),
+            Item::Bool(b) => DictKey::Bool(b),
+            Item::Executable(item) => DictKey::from(*item),
+            item => DictKey::Object(item),
+        }
+    }
+}
+
+impl From<&str> for DictKey {
+    fn from(name: &str) -> Self {
+        DictKey::Name(name.into())
+    }
+}
+
+impl From<Name> for DictKey {
+    fn from(name: Name) -> Self {
+        DictKey::Name(name)
+    }
+}
+
+impl PartialEq for DictKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DictKey::Name(a), DictKey::Name(b)) => a == b,
+            (DictKey::Number(a), DictKey::Number(b)) => a == b,
+            (DictKey::Float(a), DictKey::Float(b)) => a == b,
+            (DictKey::Bool(a), DictKey::Bool(b)) => a == b,
+            (DictKey::Object(a), DictKey::Object(b)) => a.equals(b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for DictKey {}
+
+/// Consistent with `eq`: objects that are `eq` share their address or their type.
+impl Hash for DictKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            DictKey::Name(name) => name.hash(state),
+            DictKey::Number(n) => n.hash(state),
+            DictKey::Float(bits) => bits.hash(state),
+            DictKey::Bool(b) => b.hash(state),
+            DictKey::Object(item) => match item.address() {
+                Some(address) => address.hash(state),
+                None => mem::discriminant(item).hash(state),
+            },
+        }
+    }
+}
+
+/// Formats a key the way it is reported in errors: names without their slash.
+impl fmt::Display for DictKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DictKey::Name(name) => write!(f, "{}", name),
+            key => write!(f, "{}", key.clone().into_item()),
+        }
+    }
+}
+
+/// A procedure body: an executable array of already scanned items.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub(crate) items: Array,
+    /// The location of each item in `items`, if known.
+    pub(crate) spans: Rc<[Span]>,
+}
+
+impl Block {
+    pub(crate) fn new(items: Vec<Item>, spans: Vec<Span>) -> Self {
+        Block {
+            items: Shared::new(items),
+            spans: spans.into(),
+        }
+    }
+
+    /// The item at `index`, if the procedure is long enough.
+    pub(crate) fn get(&self, index: usize) -> Option<Item> {
+        self.items.borrow().get(index).cloned()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+}
+
+/// Blocks are equal if their items are, regardless of where they were defined.
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl Eq for Block {}
+
+impl From<Vec<Item>> for Block {
+    fn from(items: Vec<Item>) -> Self {
+        Block::new(items, vec![])
+    }
 }
 
 impl From<i32> for Item {
@@ -93,7 +730,7 @@ impl From<f32> for Item {
 
 impl From<String> for Item {
     fn from(val: String) -> Self {
-        Item::Key(val)
+        Item::Key(val.into())
     }
 }
 
@@ -103,15 +740,15 @@ impl From<bool> for Item {
     }
 }
 
-impl From<HashMap<String, Item>> for Item {
-    fn from(val: HashMap<String, Item>) -> Self {
-        Item::Dict(val)
+impl From<HashMap<DictKey, Item>> for Item {
+    fn from(val: HashMap<DictKey, Item>) -> Self {
+        Item::Dict(Rc::new(Dictionary::new(val)))
     }
 }
 
 impl From<Vec<Item>> for Item {
     fn from(val: Vec<Item>) -> Self {
-        Item::Array(val)
+        Item::Array(Shared::new(val))
     }
 }
 
@@ -135,7 +772,7 @@ where
     pub fn pop(&mut self) -> Result<T> {
         self.inner
             .pop()
-            .ok_or_else(|| Report::msg("/stackunderflow"))
+            .ok_or_else(|| PsError::Stackunderflow(String::new()))
     }
 
     pub fn len(&self) -> usize {
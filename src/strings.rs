@@ -0,0 +1,212 @@
+use super::scanner::is_whitespace;
+
+/// Decode the body of a literal string, i.e. everything between the outer `(` and `)`.
+///
+/// Handles the escape sequences of the PostScript Language Reference, section 3.2.2:
+/// `\n`, `\r`, `\t`, `\b`, `\f`, `\\`, `\(`, `\)`, octal `\ddd` and `\` followed by an
+/// end-of-line marker (line continuation). Unknown escapes drop the backslash.
+/// Unescaped end-of-line markers (`\r`, `\r\n`) are stored as a single `\n`.
+pub fn decode_literal(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                i += 1;
+                let c = match bytes.get(i) {
+                    Some(&c) => c,
+                    None => break,
+                };
+                match c {
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'b' => out.push(0x08),
+                    b'f' => out.push(0x0c),
+                    b'\n' => (),
+                    b'\r' => {
+                        if bytes.get(i + 1) == Some(&b'\n') {
+                            i += 1;
+                        }
+                    }
+                    b'0'..=b'7' => {
+                        let mut n = 0u32;
+                        let mut digits = 0;
+                        while digits < 3 {
+                            match bytes.get(i) {
+                                Some(&d @ b'0'..=b'7') => {
+                                    n = n * 8 + (d - b'0') as u32;
+                                    i += 1;
+                                    digits += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        out.push(n as u8);
+                        continue;
+                    }
+                    c => out.push(c),
+                }
+                i += 1;
+            }
+            b'\r' => {
+                out.push(b'\n');
+                i += 1;
+                if bytes.get(i) == Some(&b'\n') {
+                    i += 1;
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Decode the body of a hexadecimal string, i.e. everything between `<` and `>`.
+///
+/// Whitespace is ignored. An odd number of digits behaves as if a final `0` followed.
+/// Returns `None` if a character is neither whitespace nor a hex digit.
+pub fn decode_hex(body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len() / 2);
+    let mut high = None;
+
+    for &c in body {
+        if is_whitespace(c) {
+            continue;
+        }
+        let digit = (c as char).to_digit(16)? as u8;
+        match high.take() {
+            Some(h) => out.push(h << 4 | digit),
+            None => high = Some(digit),
+        }
+    }
+
+    if let Some(h) = high {
+        out.push(h << 4);
+    }
+
+    Some(out)
+}
+
+/// Decode the body of an ASCII base-85 string, i.e. everything between `<~` and `~>`.
+///
+/// Whitespace is ignored and `z` stands for a group of four zero bytes.
+/// Returns `None` on characters outside of `!`..`u`, a misplaced `z`,
+/// a group exceeding 2^32 - 1 or a final group of a single character.
+pub fn decode_base85(body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len() * 4 / 5);
+    let mut group = [0u8; 5];
+    let mut len = 0;
+
+    for &c in body {
+        match c {
+            c if is_whitespace(c) => continue,
+            b'z' if len == 0 => out.extend_from_slice(&[0; 4]),
+            b'!'..=b'u' => {
+                group[len] = c - b'!';
+                len += 1;
+                if len == 5 {
+                    out.extend_from_slice(&base85_group(&group)?);
+                    len = 0;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    match len {
+        0 => (),
+        1 => return None,
+        n => {
+            for digit in group.iter_mut().skip(n) {
+                *digit = b'u' - b'!';
+            }
+            out.extend_from_slice(&base85_group(&group)?[..n - 1]);
+        }
+    }
+
+    Some(out)
+}
+
+fn base85_group(group: &[u8; 5]) -> Option<[u8; 4]> {
+    let value = group
+        .iter()
+        .fold(0u64, |acc, &digit| acc * 85 + digit as u64);
+    let value = u32::try_from(value).ok()?;
+    Some(value.to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_strings_are_unchanged() {
+        assert_eq!(b"Hello World".to_vec(), decode_literal(b"Hello World"));
+        assert_eq!(b"a (b) c".to_vec(), decode_literal(b"a (b) c"));
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        assert_eq!(b"a\nb\tc".to_vec(), decode_literal(br"a\nb\tc"));
+        assert_eq!(b"(\\)".to_vec(), decode_literal(br"\(\\\)"));
+        assert_eq!(vec![0x08, 0x0c], decode_literal(br"\b\f"));
+    }
+
+    #[test]
+    fn unknown_escapes_drop_the_backslash() {
+        assert_eq!(b"ax".to_vec(), decode_literal(br"\a\x"));
+    }
+
+    #[test]
+    fn octal_escapes_take_up_to_three_digits() {
+        assert_eq!(vec![0o101, b'5'], decode_literal(br"\1015"));
+        assert_eq!(vec![0o7, b'x'], decode_literal(br"\7x"));
+        assert_eq!(vec![0o53], decode_literal(br"\053"));
+    }
+
+    #[test]
+    fn line_continuation_is_dropped() {
+        assert_eq!(b"ab".to_vec(), decode_literal(b"a\\\nb"));
+        assert_eq!(b"ab".to_vec(), decode_literal(b"a\\\r\nb"));
+    }
+
+    #[test]
+    fn end_of_line_markers_are_normalized() {
+        assert_eq!(b"a\nb\nc".to_vec(), decode_literal(b"a\r\nb\rc"));
+    }
+
+    #[test]
+    fn hex_strings_are_decoded() {
+        assert_eq!(Some(b"Hello".to_vec()), decode_hex(b"48656c6C6f"));
+        assert_eq!(Some(vec![0xab, 0xcd]), decode_hex(b" ab\ncd "));
+        assert_eq!(Some(vec![0x90, 0x1f, 0xa0]), decode_hex(b"901fa"));
+        assert_eq!(Some(vec![]), decode_hex(b""));
+    }
+
+    #[test]
+    fn hex_strings_reject_bad_digits() {
+        assert_eq!(None, decode_hex(b"12g4"));
+    }
+
+    #[test]
+    fn base85_strings_are_decoded() {
+        assert_eq!(Some(b"Hello".to_vec()), decode_base85(b"87cURDZ"));
+        assert_eq!(Some(b"Hello".to_vec()), decode_base85(b"87cU\nRD Z"));
+        assert_eq!(Some(vec![0; 4]), decode_base85(b"z"));
+        assert_eq!(Some(b"test".to_vec()), decode_base85(b"FCfN8"));
+    }
+
+    #[test]
+    fn base85_strings_reject_bad_input() {
+        assert_eq!(None, decode_base85(b"87v"));
+        assert_eq!(None, decode_base85(b"8z"));
+        assert_eq!(None, decode_base85(b"87cUR8"));
+        assert_eq!(None, decode_base85(b"uuuuu"));
+    }
+}